@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::focus;
+use crate::state::{AsyncState, CardImage, ImageSlot, Page, Row, State};
+use crate::tex::{TextureHandle, TextureId};
+
+/// Set to print every action as it's dispatched
+const LOG_ACTIONS_VAR: &str = "LOG_ACTIONS";
+
+/// Everything that can change the state
+#[derive(Debug)]
+pub enum Action {
+    /// Focus a card, as `(x, y)`
+    Focus((usize, usize)),
+    /// Move focus along the current row, in cards to the right on screen
+    MoveAcross(isize),
+    /// Focus a column of the current row, clamped to its last card
+    JumpAcross(usize),
+    MoveRows(isize),
+    Top,
+    /// Open the details of the focused card
+    OpenDetails,
+    /// Open the focused row as a page of its own
+    OpenCollection,
+    OpenPage(Page),
+    /// Close the current page
+    Back,
+    /// A set that will be fetched once it's scrolled near
+    RowQueued(String),
+    RowAdded(Row),
+    /// A queued set started downloading
+    RowLoadStarted(String),
+    /// A set finished downloading, and the row it made if it could be read
+    RowLoaded(Option<Row>),
+    /// A download started for one of a card's images
+    ImageRequested {
+        card: (usize, usize),
+        slot: ImageSlot,
+        handle: TextureHandle,
+    },
+    ImageLoaded {
+        card: (usize, usize),
+        slot: ImageSlot,
+        handle: TextureHandle,
+    },
+    ImageFailed {
+        card: (usize, usize),
+        slot: ImageSlot,
+        handle: TextureHandle,
+        uri: String,
+    },
+    PreviewLoaded {
+        card: (usize, usize),
+        preview: TextureHandle,
+        full: TextureHandle,
+    },
+    /// Textures the host dropped to stay in budget, with the URIs they came from
+    TexturesEvicted(HashMap<TextureId, String>),
+    /// A frame went by, `delta_t` seconds after the last
+    Tick(f32),
+    /// Where the renderer drew each card on screen this frame
+    CardsDrawn(Vec<((usize, usize), [f32; 4])>),
+}
+
+/// Apply an action. Does no IO and doesn't wait on anything, so it can be
+/// driven directly with made up states and actions.
+pub fn reduce(state: &mut State, action: Action) {
+    let moves_focus = matches!(
+        action,
+        Action::Focus(_)
+            | Action::MoveAcross(_)
+            | Action::JumpAcross(_)
+            | Action::MoveRows(_)
+            | Action::Top
+    );
+    match action {
+        // Focus moves around whichever page is showing cards
+        Action::Focus(card) => match state.page() {
+            Page::Home => focus::focus(state, card),
+            Page::Collection(y) if card.1 == y => state.selected_card = card,
+            _ => {}
+        },
+        Action::MoveAcross(delta) => {
+            // Rows run right to left in a mirrored layout
+            let delta = if state.rtl { -delta } else { delta };
+            match state.page() {
+                Page::Home => focus::move_across(state, delta),
+                Page::Collection(_) => focus::move_in_grid(state, delta),
+                _ => {}
+            }
+        }
+        Action::JumpAcross(column) => match state.page() {
+            Page::Home => focus::jump_across(state, column),
+            Page::Collection(_) => focus::jump_in_grid_line(state, column),
+            _ => {}
+        },
+        Action::MoveRows(delta) => match state.page() {
+            Page::Home => focus::move_rows(state, delta),
+            Page::Collection(_) => focus::move_grid_lines(state, delta),
+            _ => {}
+        },
+        Action::Top => match state.page() {
+            Page::Home => focus::to_top(state),
+            Page::Collection(y) => state.selected_card = (0, y),
+            _ => {}
+        },
+        Action::OpenDetails => {
+            if let Page::Home | Page::Collection(_) = state.page() {
+                state.push_page(Page::Details(state.selected_card))
+            }
+        }
+        Action::OpenCollection => {
+            if state.page() == Page::Home {
+                state.push_page(Page::Collection(state.selected_card.1))
+            }
+        }
+        Action::OpenPage(page) => state.push_page(page),
+        Action::Back => state.pop_page(),
+        Action::RowQueued(ref_id) => state.queued_rows.push(ref_id),
+        Action::RowAdded(row) => state.rows.push(row),
+        Action::RowLoadStarted(ref_id) => {
+            state.queued_rows.retain(|queued| *queued != ref_id);
+            state.is_loading_row = true;
+        }
+        Action::RowLoaded(row) => {
+            state.rows.extend(row);
+            state.is_loading_row = false;
+            state.image_generation += 1;
+        }
+        Action::ImageRequested {
+            card: (x, y),
+            slot,
+            handle,
+        } => {
+            let image = state
+                .rows
+                .get_mut(y)
+                .and_then(|row| row.cards.get_mut(x))
+                .and_then(|card| card.image_mut(slot));
+            if let Some(image @ (CardImage::URI(_) | CardImage::Failure(_))) = image {
+                *image = CardImage::Loading(handle);
+            }
+        }
+        Action::ImageLoaded { card, slot, handle } => {
+            if let Some(image) = pending_image(state, card, slot, &handle) {
+                *image = CardImage::Texture(handle);
+            }
+        }
+        Action::ImageFailed {
+            card,
+            slot,
+            handle,
+            uri,
+        } => {
+            if let Some(image) = pending_image(state, card, slot, &handle) {
+                *image = CardImage::Failure(uri);
+            }
+        }
+        Action::PreviewLoaded {
+            card: (x, y),
+            preview,
+            full,
+        } => {
+            if let Some(card) = state.rows.get_mut(y).and_then(|row| row.cards.get_mut(x)) {
+                // Too late if the full image beat the preview here
+                if matches!(&card.image, CardImage::Loading(loading) if *loading == full) {
+                    card.image = CardImage::Preview { preview, full };
+                }
+            }
+        }
+        Action::TexturesEvicted(evicted) => state.release_textures(&evicted),
+        Action::Tick(delta_t) => state.step(delta_t),
+        Action::CardsDrawn(card_rects) => state.card_rects = card_rects,
+    }
+    if moves_focus {
+        focus::scroll_into_view(state);
+    }
+}
+
+/// Apply an action to the shared state
+pub async fn dispatch(state: &AsyncState, action: Action) {
+    apply(&mut *state.write().await, action);
+}
+
+/// Apply an action to state that's already locked, logging it if asked to
+pub fn apply(state: &mut State, action: Action) {
+    static LOG_ACTIONS: OnceLock<bool> = OnceLock::new();
+    // Every frame sends these, which would drown out everything else
+    let per_frame = matches!(action, Action::Tick(_) | Action::CardsDrawn(_));
+    if !per_frame && *LOG_ACTIONS.get_or_init(|| std::env::var_os(LOG_ACTIONS_VAR).is_some()) {
+        println!("{:?}", action);
+    }
+    reduce(state, action);
+}
+
+/// The image a download was for, unless the card has moved on to another download since
+fn pending_image<'a>(
+    state: &'a mut State,
+    (x, y): (usize, usize),
+    slot: ImageSlot,
+    handle: &TextureHandle,
+) -> Option<&'a mut CardImage> {
+    state
+        .rows
+        .get_mut(y)
+        .and_then(|row| row.cards.get_mut(x))
+        .and_then(|card| card.image_mut(slot))
+        .filter(|image| image.pending() == Some(handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anim::Anim;
+    use crate::model::collection::TILE_ASPECT;
+    use crate::state::{
+        Card, CARD_FADE_MOTION, CARD_SIZE_MOTION, COLLECTION_COLUMNS, ROW_SCROLL_MOTION,
+        TEXT_HEIGHT_MOTION,
+    };
+    use crate::tex::TextureRegistry;
+    use crate::tile::{DETAILS_TILE_SIZE, SELECTED_TILE_SIZE, TILE_SIZE};
+
+    fn card(x: usize, y: usize) -> Card {
+        Card {
+            title: format!("{}, {}", x, y),
+            image: CardImage::URI(format!("https://example.com/{}/{}.jpg", x, y)),
+            logo: None,
+            size: Anim::new(0., CARD_SIZE_MOTION),
+            fade: Anim::new(0., CARD_FADE_MOTION),
+            description: None,
+            ratings: Vec::new(),
+            releases: Vec::new(),
+        }
+    }
+
+    /// A left to right home page with rows of the given lengths
+    fn state(rows: &[usize]) -> State {
+        let mut state = State::new();
+        state.rtl = false;
+        for (y, &len) in rows.iter().enumerate() {
+            state.rows.push(Row {
+                scroll: Anim::new(0., ROW_SCROLL_MOTION),
+                text_height: Anim::new(0., TEXT_HEIGHT_MOTION),
+                aspect: TILE_ASPECT,
+                title: format!("Row {}", y),
+                cards: (0..len).map(|x| card(x, y)).collect(),
+                focused: None,
+            });
+        }
+        state
+    }
+
+    fn pages(state: &State) -> Vec<Page> {
+        state.pages.iter().map(|entry| entry.page).collect()
+    }
+
+    #[test]
+    fn focus_moves_within_rows() {
+        let mut state = state(&[5, 3, 8]);
+        reduce(&mut state, Action::Focus((2, 1)));
+        assert_eq!(state.selected_card, (2, 1));
+        reduce(&mut state, Action::MoveAcross(-1));
+        assert_eq!(state.selected_card, (1, 1));
+        // Stops at the end of the row
+        reduce(&mut state, Action::MoveAcross(10));
+        assert_eq!(state.selected_card, (2, 1));
+        reduce(&mut state, Action::MoveAcross(-10));
+        assert_eq!(state.selected_card, (0, 1));
+    }
+
+    #[test]
+    fn moving_rows_returns_to_their_focus() {
+        let mut state = state(&[5, 3, 8]);
+        reduce(&mut state, Action::Focus((4, 0)));
+        reduce(&mut state, Action::MoveRows(2));
+        assert_eq!(state.selected_card.1, 2);
+        reduce(&mut state, Action::Focus((6, 2)));
+        reduce(&mut state, Action::MoveRows(-2));
+        assert_eq!(state.selected_card, (4, 0));
+        reduce(&mut state, Action::MoveRows(5));
+        assert_eq!(state.selected_card, (6, 2));
+        reduce(&mut state, Action::Top);
+        assert_eq!(state.selected_card, (0, 0));
+    }
+
+    #[test]
+    fn moving_into_a_shorter_row_clamps_to_its_last_card() {
+        let mut state = state(&[5, 3]);
+        reduce(&mut state, Action::Focus((4, 0)));
+        reduce(&mut state, Action::MoveRows(1));
+        assert_eq!(state.selected_card, (2, 1));
+        reduce(&mut state, Action::MoveAcross(1));
+        assert_eq!(state.selected_card, (2, 1));
+    }
+
+    #[test]
+    fn moving_rows_picks_the_card_nearest_on_screen() {
+        let mut state = state(&[8, 8, 8]);
+        // Row 0 is scrolled two cards along, so its fourth card is second on screen
+        state.rows[0].scroll.set_target(2.);
+        state.rows[2].scroll.set_target(4.);
+        reduce(&mut state, Action::Focus((3, 0)));
+        reduce(&mut state, Action::MoveRows(1));
+        assert_eq!(state.selected_card, (1, 1));
+        // and second on screen in a row scrolled four along is its sixth card
+        reduce(&mut state, Action::Focus((3, 0)));
+        reduce(&mut state, Action::MoveRows(2));
+        assert_eq!(state.selected_card, (5, 2));
+    }
+
+    #[test]
+    fn moving_rows_skips_empty_rows() {
+        let mut state = state(&[5, 0, 0, 4, 0]);
+        reduce(&mut state, Action::Focus((1, 0)));
+        reduce(&mut state, Action::MoveRows(1));
+        assert_eq!(state.selected_card, (1, 3));
+        // Nothing below has cards, so focus stays put
+        reduce(&mut state, Action::MoveRows(1));
+        assert_eq!(state.selected_card, (1, 3));
+        reduce(&mut state, Action::MoveRows(-1));
+        assert_eq!(state.selected_card, (1, 0));
+    }
+
+    #[test]
+    fn back_restores_focus_and_scroll() {
+        let mut state = state(&[5, 3, 4, 4]);
+        reduce(&mut state, Action::Focus((3, 0)));
+        reduce(&mut state, Action::MoveRows(3));
+        assert_eq!(state.scroll.target(), -1.);
+        reduce(&mut state, Action::OpenDetails);
+        assert_eq!(pages(&state), [Page::Home, Page::Details((3, 3))]);
+        assert_eq!(state.scroll.target(), 0.);
+
+        // Opening the same page twice doesn't stack it
+        reduce(&mut state, Action::OpenPage(Page::Settings));
+        reduce(&mut state, Action::OpenPage(Page::Settings));
+        assert_eq!(state.pages.len(), 3);
+
+        reduce(&mut state, Action::Back);
+        reduce(&mut state, Action::Back);
+        assert_eq!(pages(&state), [Page::Home]);
+        assert_eq!(state.selected_card, (3, 3));
+        assert_eq!(state.scroll.target(), -1.);
+    }
+
+    #[test]
+    fn scrolling_follows_focus() {
+        let mut state = state(&[16, 8, 8, 8, 8]);
+        // Rows scroll once focus goes past the fourth card on screen
+        reduce(&mut state, Action::JumpAcross(3));
+        assert_eq!(state.rows[0].scroll.target(), 0.);
+        reduce(&mut state, Action::MoveAcross(2));
+        assert_eq!(state.rows[0].scroll.target(), 2.);
+        // and not back until it goes past the first
+        reduce(&mut state, Action::MoveAcross(-2));
+        assert_eq!(state.rows[0].scroll.target(), 2.);
+        reduce(&mut state, Action::MoveAcross(-2));
+        assert_eq!(state.rows[0].scroll.target(), 1.);
+
+        // The page shows three rows at once
+        reduce(&mut state, Action::MoveRows(2));
+        assert_eq!(state.scroll.target(), 0.);
+        reduce(&mut state, Action::MoveRows(2));
+        assert_eq!(state.scroll.target(), -2.);
+        reduce(&mut state, Action::Top);
+        assert_eq!(state.scroll.target(), 0.);
+        assert_eq!(state.rows[0].scroll.target(), 0.);
+
+        // and a collection three lines of its grid
+        reduce(&mut state, Action::OpenCollection);
+        reduce(&mut state, Action::MoveRows(3));
+        assert_eq!(state.scroll.target(), -1.);
+        reduce(&mut state, Action::MoveRows(-1));
+        assert_eq!(state.scroll.target(), -1.);
+    }
+
+    #[test]
+    fn ticks_grow_the_focused_card() {
+        let mut state = state(&[3]);
+        reduce(&mut state, Action::Focus((1, 0)));
+        for _ in 0..60 {
+            reduce(&mut state, Action::Tick(1. / 60.));
+        }
+        let sizes: Vec<f32> = state.rows[0]
+            .cards
+            .iter()
+            .map(|card| (card.size.value() * 100.).round() / 100.)
+            .collect();
+        assert_eq!(sizes, [TILE_SIZE, SELECTED_TILE_SIZE, TILE_SIZE]);
+        reduce(&mut state, Action::OpenDetails);
+        reduce(&mut state, Action::Tick(1. / 60.));
+        assert_eq!(state.rows[0].cards[1].size.target(), DETAILS_TILE_SIZE);
+    }
+
+    #[test]
+    fn home_cant_be_closed() {
+        let mut state = state(&[5]);
+        reduce(&mut state, Action::Focus((2, 0)));
+        reduce(&mut state, Action::Back);
+        assert_eq!(pages(&state), [Page::Home]);
+        assert_eq!(state.selected_card, (2, 0));
+    }
+
+    #[test]
+    fn stale_downloads_are_ignored() {
+        let mut state = state(&[2]);
+        let textures = TextureRegistry::default();
+        let (old, new) = (textures.allocate(), textures.allocate());
+        let slot = ImageSlot::Tile;
+        reduce(
+            &mut state,
+            Action::ImageRequested {
+                card: (0, 0),
+                slot,
+                handle: old.clone(),
+            },
+        );
+        // The first download failed and was retried
+        reduce(
+            &mut state,
+            Action::ImageFailed {
+                card: (0, 0),
+                slot,
+                handle: old.clone(),
+                uri: "https://example.com/0/0.jpg".to_owned(),
+            },
+        );
+        reduce(
+            &mut state,
+            Action::ImageRequested {
+                card: (0, 0),
+                slot,
+                handle: new.clone(),
+            },
+        );
+
+        // A late completion of the first download doesn't replace the retry
+        reduce(
+            &mut state,
+            Action::ImageLoaded {
+                card: (0, 0),
+                slot,
+                handle: old,
+            },
+        );
+        assert!(matches!(&state.rows[0].cards[0].image, CardImage::Loading(h) if *h == new));
+        reduce(
+            &mut state,
+            Action::ImageLoaded {
+                card: (0, 0),
+                slot,
+                handle: new.clone(),
+            },
+        );
+        assert!(matches!(&state.rows[0].cards[0].image, CardImage::Texture(h) if *h == new));
+    }
+
+    #[test]
+    fn evicted_images_are_wanted_again() {
+        let mut state = state(&[2]);
+        let handle = TextureRegistry::default().allocate();
+        state.rows[0].cards[1].image = CardImage::Texture(handle.clone());
+        let generation = state.image_generation;
+        let uri = "https://example.com/1/0.jpg".to_owned();
+        reduce(
+            &mut state,
+            Action::TexturesEvicted(HashMap::from([(handle.id(), uri.clone())])),
+        );
+        assert!(matches!(&state.rows[0].cards[1].image, CardImage::URI(u) if *u == uri));
+        // Prefetching looks again even though focus hasn't moved
+        assert_ne!(state.image_generation, generation);
+    }
+
+    #[test]
+    fn collections_have_their_own_focus() {
+        let mut state = state(&[5, 10]);
+        reduce(&mut state, Action::Focus((3, 1)));
+        reduce(&mut state, Action::OpenCollection);
+        assert_eq!(pages(&state), [Page::Home, Page::Collection(1)]);
+        assert_eq!(state.selected_card, (0, 1));
+
+        // Focus moves around the grid, not the home page
+        reduce(&mut state, Action::MoveRows(1));
+        reduce(&mut state, Action::MoveAcross(1));
+        assert_eq!(state.selected_card, (COLLECTION_COLUMNS + 1, 1));
+        reduce(&mut state, Action::JumpAcross(usize::MAX));
+        assert_eq!(state.selected_card, (COLLECTION_COLUMNS * 2 - 1, 1));
+        reduce(&mut state, Action::MoveRows(10));
+        assert_eq!(state.selected_card, (9, 1));
+        reduce(&mut state, Action::Focus((2, 0)));
+        assert_eq!(state.selected_card, (9, 1));
+        assert_eq!(state.rows[1].focused, Some(3));
+
+        reduce(&mut state, Action::OpenDetails);
+        assert_eq!(state.page(), Page::Details((9, 1)));
+        reduce(&mut state, Action::Back);
+        assert_eq!(state.selected_card, (9, 1));
+        reduce(&mut state, Action::Back);
+        assert_eq!(state.selected_card, (3, 1));
+    }
+}
@@ -0,0 +1,259 @@
+/// How an [`Anim`] moves from its current value towards its target.
+#[derive(Clone, Copy, Debug)]
+pub enum Motion {
+    /// Exponential decay; `rate` is how many e-foldings happen per second
+    Decay { rate: f32 },
+    /// Critically damped spring with angular frequency `omega` (never overshoots)
+    Spring { omega: f32 },
+    /// Fixed-length transition shaped by an easing curve
+    Tween { duration: f32, easing: Easing },
+}
+
+/// Easing curves for [`Motion::Tween`].
+#[derive(Clone, Copy, Debug)]
+pub enum Easing {
+    Linear,
+    /// CSS-style cubic bezier with control points `(x1, y1)` and `(x2, y2)`
+    CubicBezier(f32, f32, f32, f32),
+}
+
+impl Easing {
+    pub const EASE_OUT: Easing = Easing::CubicBezier(0., 0., 0.58, 1.);
+    pub const EASE_IN_OUT: Easing = Easing::CubicBezier(0.42, 0., 0.58, 1.);
+
+    /// Map linear progress `t` in `[0, 1]` to eased progress.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match *self {
+            Easing::Linear => t,
+            Easing::CubicBezier(x1, y1, x2, y2) => {
+                let s = solve_bezier(x1, x2, t);
+                bezier(y1, y2, s)
+            }
+        }
+    }
+}
+
+/// One axis of a cubic bezier from 0 to 1 with control values `a` and `b`.
+fn bezier(a: f32, b: f32, s: f32) -> f32 {
+    let inv = 1. - s;
+    3. * inv * inv * s * a + 3. * inv * s * s * b + s * s * s
+}
+
+fn bezier_slope(a: f32, b: f32, s: f32) -> f32 {
+    let inv = 1. - s;
+    3. * inv * inv * a + 6. * inv * s * (b - a) + 3. * s * s * (1. - b)
+}
+
+/// Find the curve parameter whose x coordinate is `x`.
+fn solve_bezier(x1: f32, x2: f32, x: f32) -> f32 {
+    // Newton's method converges in a few steps for sane curves
+    let mut s = x;
+    for _ in 0..8 {
+        let err = bezier(x1, x2, s) - x;
+        if err.abs() < 1e-5 {
+            return s;
+        }
+        let slope = bezier_slope(x1, x2, s);
+        if slope.abs() < 1e-6 {
+            break;
+        }
+        s -= err / slope;
+    }
+
+    // Fall back to bisection when the slope is too flat
+    let (mut lo, mut hi) = (0., 1.);
+    s = x;
+    for _ in 0..32 {
+        let value = bezier(x1, x2, s);
+        if (value - x).abs() < 1e-5 {
+            break;
+        }
+        if value < x {
+            lo = s;
+        } else {
+            hi = s;
+        }
+        s = (lo + hi) * 0.5;
+    }
+    s
+}
+
+/// A value animated towards a target. Every motion is solved in closed form,
+/// so stepping once by `dt` lands in the same place as stepping twice by `dt / 2`,
+/// regardless of frame rate.
+#[derive(Clone, Debug)]
+pub struct Anim {
+    value: f32,
+    velocity: f32,
+    target: f32,
+    from: f32,
+    elapsed: f32,
+    motion: Motion,
+}
+
+impl Anim {
+    pub fn new(value: f32, motion: Motion) -> Self {
+        Self {
+            value,
+            velocity: 0.,
+            target: value,
+            from: value,
+            elapsed: 0.,
+            motion,
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /// Retarget the animation. Tweens restart from the current value.
+    pub fn set_target(&mut self, target: f32) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        self.from = self.value;
+        self.elapsed = 0.;
+    }
+
+    /// Jump straight to `value` with no motion left over.
+    pub fn snap(&mut self, value: f32) {
+        self.value = value;
+        self.target = value;
+        self.from = value;
+        self.velocity = 0.;
+    }
+
+    pub fn is_settled(&self) -> bool {
+        (self.value - self.target).abs() < 1e-4 && self.velocity.abs() < 1e-4
+    }
+
+    /// Advance the animation by `dt` seconds.
+    pub fn step(&mut self, dt: f32) {
+        let dt = dt.max(0.);
+        match self.motion {
+            Motion::Decay { rate } => {
+                self.value = self.target + (self.value - self.target) * (-rate * dt).exp();
+            }
+            Motion::Spring { omega } => {
+                let x = self.value - self.target;
+                let c = self.velocity + omega * x;
+                let decay = (-omega * dt).exp();
+                self.value = self.target + (x + c * dt) * decay;
+                self.velocity = (self.velocity - omega * c * dt) * decay;
+            }
+            Motion::Tween { duration, easing } => {
+                self.elapsed += dt;
+                let t = if duration > 0. {
+                    self.elapsed / duration
+                } else {
+                    1.
+                };
+                self.value = self.from + (self.target - self.from) * easing.apply(t);
+            }
+        }
+        if self.is_settled() {
+            self.value = self.target;
+            self.velocity = 0.;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOTIONS: [Motion; 4] = [
+        Motion::Decay { rate: 12. },
+        Motion::Spring { omega: 14. },
+        Motion::Tween {
+            duration: 0.3,
+            easing: Easing::EASE_IN_OUT,
+        },
+        Motion::Tween {
+            duration: 0.2,
+            easing: Easing::Linear,
+        },
+    ];
+
+    /// Run an animation from 0 to 1 for `seconds`, in steps of `dt`, recording every value
+    fn run(motion: Motion, dt: f32, seconds: f32) -> Vec<f32> {
+        let mut anim = Anim::new(0., motion);
+        anim.set_target(1.);
+        (0..(seconds / dt).round() as usize)
+            .map(|_| {
+                anim.step(dt);
+                anim.value()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converges_to_target() {
+        for motion in MOTIONS {
+            let values = run(motion, 1. / 60., 2.);
+            assert_eq!(*values.last().unwrap(), 1., "{:?}", motion);
+        }
+    }
+
+    #[test]
+    fn settled_anim_stays_put() {
+        for motion in MOTIONS {
+            let mut anim = Anim::new(0.5, motion);
+            anim.step(1. / 60.);
+            assert_eq!(anim.value(), 0.5);
+            assert!(anim.is_settled());
+        }
+    }
+
+    #[test]
+    fn spring_does_not_overshoot() {
+        for dt in [1. / 240., 1. / 60., 1. / 15., 0.5] {
+            let values = run(Motion::Spring { omega: 14. }, dt, 2.);
+            assert!(values.iter().all(|&v| v <= 1.), "dt {}", dt);
+            assert!(values.windows(2).all(|w| w[0] <= w[1]), "dt {}", dt);
+        }
+    }
+
+    #[test]
+    fn independent_of_frame_rate() {
+        for motion in MOTIONS {
+            let slow = run(motion, 1. / 30., 1.);
+            let fast = run(motion, 1. / 120., 1.);
+            // Every 30 fps frame lines up with every fourth 120 fps frame
+            for (i, &value) in slow.iter().enumerate() {
+                let other = fast[i * 4 + 3];
+                assert!((value - other).abs() < 1e-3, "{:?} at frame {}", motion, i);
+            }
+        }
+    }
+
+    #[test]
+    fn tween_finishes_on_time() {
+        let values = run(
+            Motion::Tween {
+                duration: 0.25,
+                easing: Easing::EASE_OUT,
+            },
+            0.05,
+            0.3,
+        );
+        assert!(values[3] < 1.);
+        assert_eq!(values[4], 1.);
+    }
+
+    #[test]
+    fn easing_keeps_its_ends() {
+        for easing in [Easing::Linear, Easing::EASE_OUT, Easing::EASE_IN_OUT] {
+            assert!(easing.apply(0.).abs() < 1e-4);
+            assert!((easing.apply(1.) - 1.).abs() < 1e-4);
+            assert!(easing.apply(0.25) < easing.apply(0.75));
+        }
+    }
+}
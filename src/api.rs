@@ -1,11 +1,16 @@
-use crate::{model::collection, state};
+use crate::{
+    action::{self, Action},
+    cache::SharedImageCache,
+    model::collection,
+    state,
+    tex::{QueuedImage, QueuedImages, TextureHandle, TextureRegistry},
+};
+use image::ImageFormat;
+use reqwest::{header::ACCEPT, Url};
 use std::{io::Cursor, sync::Arc};
 use tokio::sync::RwLock;
 
-pub async fn load_home(
-    state: Arc<RwLock<state::State>>,
-    queued_images: Arc<RwLock<Vec<(u32, image::DynamicImage)>>>,
-) {
+pub async fn load_home(state: Arc<RwLock<state::State>>) {
     let body = reqwest::get("https://cd-static.bamgrid.com/dp-117731241344/home.json")
         .await
         .unwrap();
@@ -28,84 +33,302 @@ pub async fn load_home(
                     ref_id_type: _,
                     ref_type: _,
                 } => {
-                    let ref_id = ref_id.clone();
-                    let mut state = state.write().await;
-                    state.queued_rows.push(ref_id);
+                    action::dispatch(&state, Action::RowQueued(ref_id.clone())).await;
                     None
                 }
             };
             if let Some(row) = row {
-                let mut state = state.write().await;
-                state.rows.push(row);
+                action::dispatch(&state, Action::RowAdded(row)).await;
             }
         }
     }
-    let response = reqwest::get("https://prod-ripcut-delivery.disney-plus.net/v1/variant/disney/CD3FC43E25A8722F8264FD65BB0F534FAAD5312DE01E5E949875E2AFB316022B/scale?format=jpeg&quality=90&scalingAlgorithm=lanczos3&width=500").await.unwrap();
-    let cursor = Cursor::new(response.bytes().await.unwrap());
-    let img = image::io::Reader::new(cursor)
-        .with_guessed_format()
-        .unwrap()
-        .decode()
-        .unwrap();
+}
 
-    {
-        let mut queued_images = queued_images.write().await;
-        queued_images.push((0u32, img));
+/// Everything needed to fetch card images in the background
+#[derive(Clone)]
+pub struct ImageLoader {
+    pub state: state::AsyncState,
+    pub textures: TextureRegistry,
+    pub queued_images: QueuedImages,
+    pub image_cache: SharedImageCache,
+    pub client: reqwest::Client,
+}
+
+/// Formats ripcut may pick from, smallest first. AVIF decoding needs the `avif` feature.
+const ACCEPT_COMPRESSED: &str = if cfg!(feature = "avif") {
+    "image/avif,image/webp;q=0.9,image/jpeg;q=0.8"
+} else {
+    "image/webp,image/jpeg;q=0.8"
+};
+
+/// Width of the tiny variant fetched ahead of a tile's full artwork
+const PREVIEW_WIDTH: u32 = 48;
+
+impl ImageLoader {
+    /// Kick off the download of one of a card's images, sized for a tile that is
+    /// `pixel_width` pixels wide on screen. Returns the handle it'll be uploaded under,
+    /// which the card should hold on to with [`Action::ImageRequested`].
+    pub fn load(
+        &self,
+        uri: &str,
+        card: (usize, usize),
+        slot: state::ImageSlot,
+        pixel_width: f32,
+    ) -> TextureHandle {
+        let handle = self.textures.allocate();
+        if let state::ImageSlot::Tile = slot {
+            // The preview usually arrives well before the full image,
+            // and looks like a blur once it's scaled up to the tile
+            let preview_uri = with_width(uri, PREVIEW_WIDTH);
+            tokio::spawn(load_preview(
+                self.clone(),
+                preview_uri,
+                card,
+                handle.clone(),
+            ));
+        }
+        let uri = with_width(uri, rounded_width(pixel_width));
+        tokio::spawn(load_card_image(
+            self.clone(),
+            uri,
+            card,
+            slot,
+            handle.clone(),
+        ));
+        handle
+    }
+}
+
+/// Drop the `format` parameter from a ripcut URI so the format is picked from the `Accept` header
+fn without_format(uri: &str) -> String {
+    with_query(uri, "format", None)
+}
+
+/// Round a width up to the next hundred pixels so tiles of similar sizes share cache entries
+fn rounded_width(width: f32) -> u32 {
+    ((width / 100.).ceil().max(1.) as u32 * 100).min(2000)
+}
+
+/// Point a ripcut URI at a variant `width` pixels wide
+fn with_width(uri: &str, width: u32) -> String {
+    with_query(uri, "width", Some(&width.to_string()))
+}
+
+/// Set a query parameter of a URI, or remove it when `value` is `None`,
+/// leaving the others as they were
+pub fn with_query(uri: &str, key: &str, value: Option<&str>) -> String {
+    let mut url = match Url::parse(uri) {
+        Ok(url) => url,
+        Err(e) => {
+            println!("error while parsing {}: {}", uri, e);
+            return uri.to_owned();
+        }
+    };
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| k != key)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    if pairs.is_empty() && value.is_none() {
+        url.set_query(None);
+    } else {
+        let mut query = url.query_pairs_mut();
+        query.clear().extend_pairs(pairs);
+        if let Some(value) = value {
+            query.append_pair(key, value);
+        }
     }
+    url.into()
 }
 
-pub async fn load_card_image(
+async fn load_card_image(
+    loader: ImageLoader,
     uri: String,
-    state: state::AsyncState,
-    queued_images: Arc<RwLock<Vec<(u32, image::DynamicImage)>>>,
-    x: usize,
-    y: usize,
-    uid: u32,
+    card: (usize, usize),
+    slot: state::ImageSlot,
+    handle: TextureHandle,
 ) {
-    let response = reqwest::get(uri).await.unwrap();
-    let cursor = Cursor::new(response.bytes().await.unwrap());
-    let img = image::io::Reader::new(cursor)
-        .with_guessed_format()
-        .unwrap()
-        .decode()
-        .unwrap();
+    let img = fetch_negotiated(&loader, &uri).await;
+    let img_loaded = img.is_some();
 
-    {
-        let mut queued_images = queued_images.write().await;
-        queued_images.push((uid, img));
+    if let Some(img) = img {
+        let mut queued_images = loader.queued_images.write().await;
+        queued_images.push(QueuedImage {
+            handle: handle.clone(),
+            uri: uri.clone(),
+            image: img,
+        });
     }
+    let action = if img_loaded {
+        Action::ImageLoaded { card, slot, handle }
+    } else {
+        Action::ImageFailed {
+            card,
+            slot,
+            handle,
+            uri,
+        }
+    };
+    action::dispatch(&loader.state, action).await;
+}
+
+/// Show a low resolution version of a tile until the download of `full` finishes
+async fn load_preview(loader: ImageLoader, uri: String, card: (usize, usize), full: TextureHandle) {
+    let img = match fetch_negotiated(&loader, &uri).await {
+        Some(img) => img,
+        None => return,
+    };
+    let preview = loader.textures.allocate();
     {
-        let mut state = state.write().await;
-        state
-            .rows
-            .get_mut(y as usize)
-            .and_then(|row| row.cards.get_mut(x as usize))
-            .map(|card| card.image = state::CardImage::Texture(uid));
+        let mut queued_images = loader.queued_images.write().await;
+        queued_images.push(QueuedImage {
+            handle: preview.clone(),
+            uri,
+            image: img,
+        });
     }
+    action::dispatch(
+        &loader.state,
+        Action::PreviewLoaded {
+            card,
+            preview,
+            full,
+        },
+    )
+    .await;
 }
 
-pub async fn load_next_row(state: state::AsyncState) -> Option<()> {
+/// Let the server send WebP or AVIF when it can, and fall back to the
+/// format the URI names (JPEG or PNG) if that doesn't download or decode
+async fn fetch_negotiated(loader: &ImageLoader, uri: &str) -> Option<image::DynamicImage> {
+    match fetch_image(loader, &without_format(uri), ACCEPT_COMPRESSED).await {
+        Some(img) => Some(img),
+        None => fetch_image(loader, uri, "image/jpeg,image/png;q=0.9").await,
+    }
+}
+
+/// Download (or pull from the cache) and decode an image, logging whatever went wrong
+async fn fetch_image(loader: &ImageLoader, uri: &str, accept: &str) -> Option<image::DynamicImage> {
+    let image_cache = &loader.image_cache;
+    let cached = image_cache
+        .write()
+        .await
+        .get(uri)
+        .map(|bytes| bytes.to_vec());
+    let was_cached = cached.is_some();
+    let bytes = match cached {
+        Some(bytes) => bytes,
+        None => {
+            let response = loader
+                .client
+                .get(uri)
+                .header(ACCEPT, accept)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| println!("error while fetching {}: {}", uri, e))
+                .ok()?;
+            response
+                .bytes()
+                .await
+                .map_err(|e| println!("error while fetching {}: {}", uri, e))
+                .ok()?
+                .to_vec()
+        }
+    };
+    let format = sniff_format(&bytes)
+        .map_err(|e| println!("error while decoding {}: {}", uri, e))
+        .ok()?;
+    let img = image::io::Reader::with_format(Cursor::new(&bytes), format)
+        .decode()
+        .map_err(|e| println!("error while decoding {}: {}", uri, e))
+        .ok()?;
+
+    // Only keep bytes that are known to decode
+    if !was_cached {
+        image_cache.write().await.insert(uri.to_owned(), bytes);
+    }
+    Some(img)
+}
+
+/// Work out an image's format from its first bytes. `image` only recognises
+/// AVIF files whose `ftyp` box is exactly 32 bytes, so that's checked here.
+fn sniff_format(bytes: &[u8]) -> image::ImageResult<ImageFormat> {
+    match bytes.get(4..12) {
+        Some(b"ftypavif") | Some(b"ftypavis") => Ok(ImageFormat::Avif),
+        _ => image::guess_format(bytes),
+    }
+}
+
+/// Fetch a queued set, which should already have been marked as loading
+pub async fn load_next_row(state: state::AsyncState, ref_id: String) {
     println!("Loading next row");
-    let ref_id = {
-        let mut state = state.write().await;
-        state.queued_rows.pop()
-    }?;
+    let row = fetch_row(&ref_id).await;
+    action::dispatch(&state, Action::RowLoaded(row)).await;
+    println!("Done loading row");
+}
+
+async fn fetch_row(ref_id: &str) -> Option<state::Row> {
     let body = reqwest::get(format!(
         "https://cd-static.bamgrid.com/dp-117731241344/sets/{}.json",
         ref_id
     ))
     .await
-    .unwrap();
+    .map_err(|e| println!("error while fetching set {}: {}", ref_id, e))
+    .ok()?;
     let x = body
         .json::<collection::RefSet>()
         .await
-        .map_err(|e| println!("{} {}", ref_id, e))
-        .unwrap();
-    let row: Option<state::Row> = (&x.data.set).into();
-    if let Some(row) = row {
-        let mut state = state.write().await;
-        state.rows.push(row);
+        .map_err(|e| println!("error while parsing set {}: {}", ref_id, e))
+        .ok()?;
+    (&x.data.set).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str =
+        "https://example.com/v1/variant/disney/ABC/scale?format=jpeg&quality=90&width=500";
+
+    #[test]
+    fn sets_existing_parameter() {
+        assert_eq!(
+            with_width(URI, 300),
+            "https://example.com/v1/variant/disney/ABC/scale?format=jpeg&quality=90&width=300"
+        );
+        assert_eq!(
+            with_query(URI, "format", Some("png")),
+            "https://example.com/v1/variant/disney/ABC/scale?quality=90&width=500&format=png"
+        );
+    }
+
+    #[test]
+    fn adds_missing_parameter() {
+        assert_eq!(
+            with_query("https://example.com/a", "format", Some("png")),
+            "https://example.com/a?format=png"
+        );
+        assert_eq!(
+            with_width("https://example.com/a?quality=90", 100),
+            "https://example.com/a?quality=90&width=100"
+        );
+    }
+
+    #[test]
+    fn removes_parameter() {
+        assert_eq!(
+            without_format(URI),
+            "https://example.com/v1/variant/disney/ABC/scale?quality=90&width=500"
+        );
+        assert_eq!(
+            without_format("https://example.com/a?format=jpeg"),
+            "https://example.com/a"
+        );
+    }
+
+    #[test]
+    fn leaves_unparseable_uri_alone() {
+        assert_eq!(with_width("not a uri", 100), "not a uri");
     }
-    println!("Done loading row");
-    Some(())
 }
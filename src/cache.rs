@@ -0,0 +1,86 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use tokio::sync::RwLock;
+
+/// In-memory cache of encoded image bytes, so textures that were evicted
+/// from the GPU can be rebuilt without going back to the network.
+/// The least recently used entries are dropped first once the budget is exceeded.
+pub struct ImageCache {
+    entries: HashMap<String, Vec<u8>>,
+    /// Least recently used first
+    order: VecDeque<String>,
+    budget: usize,
+    size: usize,
+}
+
+pub type SharedImageCache = Arc<RwLock<ImageCache>>;
+
+impl ImageCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            budget,
+            size: 0,
+        }
+    }
+
+    /// Look up an image, marking it as the most recently used
+    pub fn get(&mut self, uri: &str) -> Option<&[u8]> {
+        let bytes = self.entries.get(uri)?;
+        if let Some(index) = self.order.iter().position(|entry| entry == uri) {
+            let entry = self.order.remove(index).expect("index in range");
+            self.order.push_back(entry);
+        }
+        Some(bytes.as_slice())
+    }
+
+    pub fn insert(&mut self, uri: String, bytes: Vec<u8>) {
+        if bytes.len() > self.budget || self.entries.contains_key(&uri) {
+            return;
+        }
+        self.size += bytes.len();
+        self.order.push_back(uri.clone());
+        self.entries.insert(uri, bytes);
+        while self.size > self.budget {
+            let oldest = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if let Some(bytes) = self.entries.remove(&oldest) {
+                self.size -= bytes.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ImageCache::new(30);
+        cache.insert("a".to_owned(), vec![0; 10]);
+        cache.insert("b".to_owned(), vec![0; 10]);
+        cache.insert("c".to_owned(), vec![0; 10]);
+        assert!(cache.get("a").is_some());
+        cache.insert("d".to_owned(), vec![0; 10]);
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("c").is_some());
+        assert!(cache.get("d").is_some());
+    }
+
+    #[test]
+    fn skips_images_over_budget() {
+        let mut cache = ImageCache::new(10);
+        cache.insert("a".to_owned(), vec![0; 5]);
+        cache.insert("b".to_owned(), vec![0; 11]);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+    }
+}
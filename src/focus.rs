@@ -0,0 +1,140 @@
+use crate::state::{Page, State, COLLECTION_COLUMNS};
+
+/// Cards a home page row shows at once
+const ROW_VIEW_CARDS: usize = 4;
+/// Rows, or lines of a collection's grid, a page shows at once
+const PAGE_VIEW_LINES: usize = 3;
+
+/// Focus a card and remember it as the one to come back to in its row
+pub fn focus(state: &mut State, card: (usize, usize)) {
+    if let Some(row) = state.rows.get_mut(card.1) {
+        row.focused = Some(card.0);
+    }
+    state.selected_card = card;
+}
+
+/// Move focus along the current row, stopping at either end
+pub fn move_across(state: &mut State, delta: isize) {
+    let (x, y) = state.selected_card;
+    let len = match state.rows.get(y) {
+        Some(row) if !row.cards.is_empty() => row.cards.len(),
+        _ => return,
+    };
+    let new = (x as isize + delta).clamp(0, len as isize - 1) as usize;
+    focus(state, (new, y));
+}
+
+/// Focus the card at `column` in the current row, or the last one if the row is shorter
+pub fn jump_across(state: &mut State, column: usize) {
+    let y = state.selected_card.1;
+    let len = match state.rows.get(y) {
+        Some(row) if !row.cards.is_empty() => row.cards.len(),
+        _ => return,
+    };
+    focus(state, (column.min(len - 1), y));
+}
+
+/// Move focus `delta` rows up or down. Rows that have been visited before get back
+/// the card they last had focused; otherwise the card nearest on screen is picked.
+pub fn move_rows(state: &mut State, delta: isize) {
+    let (x, y) = state.selected_card;
+    if state.rows.is_empty() {
+        return;
+    }
+    let target = (y as isize + delta).clamp(0, state.rows.len() as isize - 1) as usize;
+
+    // Rows without cards can't take focus, so stop short of them,
+    // or go on past them if there's nothing with cards before
+    let candidates: Vec<usize> = if target > y {
+        (y + 1..=target)
+            .rev()
+            .chain(target + 1..state.rows.len())
+            .collect()
+    } else {
+        (target..y).chain((0..target).rev()).collect()
+    };
+    let target = match candidates
+        .into_iter()
+        .find(|&row| !state.rows[row].cards.is_empty())
+    {
+        Some(target) => target,
+        None => return,
+    };
+
+    let row = &state.rows[target];
+    let column = row.focused.unwrap_or_else(|| {
+        // Compare where the cards will be once the rows finish scrolling
+        let on_screen = x as f32 - state.rows.get(y).map_or(0., |row| row.scroll.target());
+        (on_screen + row.scroll.target()).round().max(0.) as usize
+    });
+    let column = column.min(row.cards.len() - 1);
+    focus(state, (column, target));
+}
+
+/// Scroll the focused card's row and page just far enough to bring it fully on screen
+pub fn scroll_into_view(state: &mut State) {
+    let (x, y) = state.selected_card;
+    let line = match state.page() {
+        Page::Home => {
+            if let Some(row) = state.rows.get_mut(y) {
+                let first = x as f32 + 1. - ROW_VIEW_CARDS as f32;
+                row.scroll
+                    .set_target(row.scroll.target().clamp(first, x as f32));
+            }
+            y
+        }
+        Page::Collection(_) => x / COLLECTION_COLUMNS,
+        _ => return,
+    };
+    // Pages scroll down as the target goes negative
+    let last = (PAGE_VIEW_LINES - 1) as f32 - line as f32;
+    state
+        .scroll
+        .set_target(state.scroll.target().clamp(-(line as f32), last));
+}
+
+/// Go back to the start of the first row that has cards
+pub fn to_top(state: &mut State) {
+    if let Some(y) = state.rows.iter().position(|row| !row.cards.is_empty()) {
+        focus(state, (0, y));
+    }
+}
+
+/// Move focus `delta` cards through a collection's grid in reading order, stopping at either end
+pub fn move_in_grid(state: &mut State, delta: isize) {
+    let (x, y) = state.selected_card;
+    let len = match state.rows.get(y) {
+        Some(row) if !row.cards.is_empty() => row.cards.len(),
+        _ => return,
+    };
+    let new = (x as isize + delta).clamp(0, len as isize - 1) as usize;
+    state.selected_card = (new, y);
+}
+
+/// Move focus `delta` lines up or down a collection's grid, keeping to the same column
+/// where the last line is short enough to allow it
+pub fn move_grid_lines(state: &mut State, delta: isize) {
+    let (x, y) = state.selected_card;
+    let len = match state.rows.get(y) {
+        Some(row) if !row.cards.is_empty() => row.cards.len(),
+        _ => return,
+    };
+    let last_line = (len - 1) / COLLECTION_COLUMNS;
+    let line = (x / COLLECTION_COLUMNS) as isize + delta;
+    let line = line.clamp(0, last_line as isize) as usize;
+    let new = (line * COLLECTION_COLUMNS + x % COLLECTION_COLUMNS).min(len - 1);
+    state.selected_card = (new, y);
+}
+
+/// Focus the card at `column` of the focused line of a collection's grid,
+/// or the last one if the line is shorter
+pub fn jump_in_grid_line(state: &mut State, column: usize) {
+    let (x, y) = state.selected_card;
+    let len = match state.rows.get(y) {
+        Some(row) if !row.cards.is_empty() => row.cards.len(),
+        _ => return,
+    };
+    let start = x - x % COLLECTION_COLUMNS;
+    let column = column.min(COLLECTION_COLUMNS - 1);
+    state.selected_card = ((start + column).min(len - 1), y);
+}
@@ -0,0 +1,300 @@
+use glfw::{Action, GamepadAxis, GamepadButton, Glfw, JoystickId};
+
+use crate::input;
+use crate::keymap::Command;
+
+/// Buttons and the commands they send, matching what a TV remote would do
+const BUTTON_BINDINGS: [(GamepadButton, Command); 12] = [
+    (GamepadButton::ButtonDpadUp, Command::MoveUp),
+    (GamepadButton::ButtonDpadDown, Command::MoveDown),
+    (GamepadButton::ButtonDpadLeft, Command::MoveLeft),
+    (GamepadButton::ButtonDpadRight, Command::MoveRight),
+    (GamepadButton::ButtonA, Command::Select),
+    (GamepadButton::ButtonB, Command::Back),
+    (GamepadButton::ButtonBack, Command::Back),
+    (GamepadButton::ButtonLeftBumper, Command::PageUp),
+    (GamepadButton::ButtonRightBumper, Command::PageDown),
+    (GamepadButton::ButtonY, Command::Top),
+    (GamepadButton::ButtonX, Command::OpenRow),
+    (GamepadButton::ButtonStart, Command::Settings),
+];
+/// Stick deflection that starts moving
+const STICK_PRESS: f32 = 0.5;
+/// Stick deflection below which it stops moving. Lower than `STICK_PRESS` so a
+/// stick resting near the threshold doesn't flicker between the two.
+const STICK_RELEASE: f32 = 0.3;
+/// Seconds a direction is held before it starts repeating
+const REPEAT_DELAY: f32 = 0.4;
+/// Seconds between repeats at the threshold and at full deflection
+const REPEAT_INTERVAL_SLOW: f32 = 0.2;
+const REPEAT_INTERVAL_FAST: f32 = 0.06;
+
+/// A snapshot of a gamepad. Comes from glfw, but can just as well be made up
+/// and fed to [`Gamepad::update`] to drive navigation without a controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PadState {
+    /// Indexed by `GamepadButton as usize`
+    pub buttons: [bool; 15],
+    /// Left stick, with x to the right and y down like glfw reports it
+    pub stick: [f32; 2],
+}
+
+impl PadState {
+    pub fn pressed(&self, button: GamepadButton) -> bool {
+        self.buttons[button as usize]
+    }
+
+    /// The direction the stick is pushed in, if it's pushed past `threshold`,
+    /// along with how far it's pushed
+    fn stick_direction(&self, threshold: f32) -> Option<(Command, f32)> {
+        let [x, y] = self.stick;
+        let (command, amount) = if x.abs() > y.abs() {
+            let command = if x > 0. {
+                Command::MoveRight
+            } else {
+                Command::MoveLeft
+            };
+            (command, x.abs())
+        } else {
+            let command = if y > 0. {
+                Command::MoveDown
+            } else {
+                Command::MoveUp
+            };
+            (command, y.abs())
+        };
+        Some((command, amount)).filter(|_| amount > threshold)
+    }
+}
+
+/// Read the first connected gamepad
+pub fn poll(glfw: &Glfw) -> Option<PadState> {
+    (0..16)
+        .filter_map(JoystickId::from_i32)
+        .map(|id| glfw.get_joystick(id))
+        .filter(|joystick| joystick.is_gamepad())
+        .find_map(|joystick| joystick.get_gamepad_state())
+        .map(|state| {
+            let mut pad = PadState::default();
+            for (i, pressed) in pad.buttons.iter_mut().enumerate() {
+                *pressed = GamepadButton::from_i32(i as i32)
+                    .is_some_and(|button| state.get_button_state(button) == Action::Press);
+            }
+            pad.stick = [
+                state.get_axis(GamepadAxis::AxisLeftX),
+                state.get_axis(GamepadAxis::AxisLeftY),
+            ];
+            pad
+        })
+}
+
+struct Held {
+    command: Command,
+    from_stick: bool,
+    held_for: f32,
+    until_repeat: f32,
+}
+
+/// Turns successive gamepad states into the same commands the keyboard sends
+#[derive(Default)]
+pub struct Gamepad {
+    previous: PadState,
+    /// The direction the stick was last pushed in
+    stick: Option<Command>,
+    /// The last movement pressed, which repeats while it's held
+    held: Option<Held>,
+}
+
+impl Gamepad {
+    /// Compare `pad` with the last state seen, `delta_t` seconds ago
+    pub fn update(&mut self, pad: PadState, delta_t: f32) -> Vec<(Command, Action)> {
+        let mut commands = Vec::new();
+
+        for &(button, command) in &BUTTON_BINDINGS {
+            match (self.previous.pressed(button), pad.pressed(button)) {
+                (false, true) => {
+                    commands.push((command, Action::Press));
+                    self.hold(command, false);
+                }
+                (true, false) => {
+                    commands.push((command, Action::Release));
+                    self.release(command);
+                }
+                _ => {}
+            }
+        }
+
+        // Keep moving the same way until the stick is nearly centred again
+        let stick = match self.stick {
+            Some(command) => match pad.stick_direction(STICK_RELEASE) {
+                Some((direction, _)) if direction == command => Some(command),
+                _ => pad
+                    .stick_direction(STICK_PRESS)
+                    .map(|(direction, _)| direction),
+            },
+            None => pad
+                .stick_direction(STICK_PRESS)
+                .map(|(direction, _)| direction),
+        };
+        if stick != self.stick {
+            if let Some(command) = self.stick {
+                commands.push((command, Action::Release));
+                self.release(command);
+            }
+            if let Some(command) = stick {
+                commands.push((command, Action::Press));
+                self.hold(command, true);
+            }
+            self.stick = stick;
+        }
+
+        if let Some(held) = self.held.as_mut() {
+            held.held_for += delta_t;
+            held.until_repeat -= delta_t;
+            // At most one repeat a frame, so a stalled frame doesn't send a burst of moves.
+            // Along rows that one repeat can still be accelerated into several steps.
+            if held.until_repeat <= 0. {
+                let steps = input::repeat_steps(held.command, held.held_for);
+                commands.extend(std::iter::repeat_n((held.command, Action::Repeat), steps));
+                // The further the stick is pushed, the faster it repeats
+                let push = match pad.stick_direction(0.) {
+                    Some((_, amount)) if held.from_stick => {
+                        ((amount - STICK_PRESS) / (1. - STICK_PRESS)).clamp(0., 1.)
+                    }
+                    _ => 0.,
+                };
+                let interval =
+                    REPEAT_INTERVAL_SLOW + (REPEAT_INTERVAL_FAST - REPEAT_INTERVAL_SLOW) * push;
+                // Repeats missed during a stall are dropped, keeping to the same beat
+                held.until_repeat = interval - -held.until_repeat % interval;
+            }
+        }
+
+        self.previous = pad;
+        commands
+    }
+
+    fn hold(&mut self, command: Command, from_stick: bool) {
+        if input::repeats(command) {
+            self.held = Some(Held {
+                command,
+                from_stick,
+                held_for: 0.,
+                until_repeat: REPEAT_DELAY,
+            });
+        }
+    }
+
+    fn release(&mut self, command: Command) {
+        if self.held.as_ref().map(|held| held.command) == Some(command) {
+            self.held = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f32 = 1. / 60.;
+
+    fn pad(buttons: &[GamepadButton], stick: [f32; 2]) -> PadState {
+        let mut pad = PadState {
+            stick,
+            ..PadState::default()
+        };
+        for &button in buttons {
+            pad.buttons[button as usize] = true;
+        }
+        pad
+    }
+
+    /// Hold a state for `seconds`, a frame at a time, collecting every command sent
+    fn hold_for(gamepad: &mut Gamepad, pad: PadState, seconds: f32) -> Vec<(Command, Action)> {
+        let frames = (seconds / FRAME).round() as usize;
+        (0..frames)
+            .flat_map(|_| gamepad.update(pad, FRAME))
+            .collect()
+    }
+
+    fn repeats(commands: &[(Command, Action)]) -> usize {
+        commands
+            .iter()
+            .filter(|(_, action)| *action == Action::Repeat)
+            .count()
+    }
+
+    #[test]
+    fn buttons_press_and_release() {
+        let mut gamepad = Gamepad::default();
+        let a = pad(&[GamepadButton::ButtonA], [0., 0.]);
+        assert_eq!(
+            gamepad.update(a, FRAME),
+            vec![(Command::Select, Action::Press)]
+        );
+        // Selecting doesn't repeat however long it's held
+        assert!(hold_for(&mut gamepad, a, 2.).is_empty());
+        assert_eq!(
+            gamepad.update(PadState::default(), FRAME),
+            vec![(Command::Select, Action::Release)]
+        );
+    }
+
+    #[test]
+    fn held_direction_repeats() {
+        let mut gamepad = Gamepad::default();
+        let down = pad(&[GamepadButton::ButtonDpadDown], [0., 0.]);
+        assert_eq!(
+            gamepad.update(down, FRAME),
+            vec![(Command::MoveDown, Action::Press)]
+        );
+        assert_eq!(
+            repeats(&hold_for(&mut gamepad, down, REPEAT_DELAY - 0.05)),
+            0
+        );
+        let commands = hold_for(&mut gamepad, down, 1.05);
+        assert_eq!(repeats(&commands), 1 + (1. / REPEAT_INTERVAL_SLOW) as usize);
+        assert!(commands
+            .iter()
+            .all(|&(command, _)| command == Command::MoveDown));
+    }
+
+    #[test]
+    fn stall_sends_one_repeat() {
+        let mut gamepad = Gamepad::default();
+        let down = pad(&[GamepadButton::ButtonDpadDown], [0., 0.]);
+        gamepad.update(down, FRAME);
+        assert_eq!(repeats(&gamepad.update(down, 5.)), 1);
+        // and carries on at the usual pace afterwards
+        assert_eq!(repeats(&hold_for(&mut gamepad, down, FRAME)), 0);
+        assert_eq!(repeats(&hold_for(&mut gamepad, down, 1.)), 5);
+    }
+
+    #[test]
+    fn stall_sends_one_accelerated_repeat() {
+        let mut gamepad = Gamepad::default();
+        let right = pad(&[GamepadButton::ButtonDpadRight], [0., 0.]);
+        gamepad.update(right, FRAME);
+        let commands = gamepad.update(right, 5.);
+        // One repeat, sent as however many steps it's accelerated to by now
+        let steps = input::repeat_steps(Command::MoveRight, FRAME + 5.);
+        assert!(steps > 1);
+        assert_eq!(commands, vec![(Command::MoveRight, Action::Repeat); steps]);
+        assert_eq!(repeats(&hold_for(&mut gamepad, right, FRAME)), 0);
+    }
+
+    #[test]
+    fn stick_needs_centering_to_release() {
+        let mut gamepad = Gamepad::default();
+        assert!(gamepad.update(pad(&[], [0.4, 0.]), FRAME).is_empty());
+        assert_eq!(
+            gamepad.update(pad(&[], [0.6, 0.1]), FRAME),
+            vec![(Command::MoveRight, Action::Press)]
+        );
+        assert!(gamepad.update(pad(&[], [0.4, 0.]), FRAME).is_empty());
+        assert_eq!(
+            gamepad.update(pad(&[], [0.2, 0.]), FRAME),
+            vec![(Command::MoveRight, Action::Release)]
+        );
+    }
+}
@@ -1,54 +1,309 @@
-use glfw::{Action, Key, WindowEvent};
+use std::time::Instant;
 
-use crate::state::AsyncState;
+use glfw::{Action as KeyAction, MouseButton, WindowEvent};
 
-pub async fn handle_event(event: WindowEvent, state: AsyncState) -> bool {
-    let mut state = state.write().await;
-    match event {
-        WindowEvent::Close | WindowEvent::Key(Key::Escape, _, Action::Release, _) => return false,
-        WindowEvent::Key(Key::Backspace, _, Action::Press, _) => state.show_modal = false,
-        _ => {}
-    }
+use crate::action::{self, Action};
+use crate::keymap::{Command, Keymap};
+use crate::state::{AsyncState, Page, State};
+
+/// Rows moved by PageUp and PageDown
+const PAGE_ROWS: usize = 3;
+/// Seconds a move along a row is held before each repeat moves further, and the
+/// most cards a single repeat can move
+const REPEAT_ACCELERATE_AFTER: f32 = 0.8;
+const REPEAT_MAX_STEPS: usize = 4;
+
+/// Keyboard and mouse input, turned into commands
+pub struct Input {
+    keymap: Keymap,
+    /// Cursor position in window coordinates
+    cursor: (f64, f64),
+    /// Window size in the same coordinates as the cursor
+    window_size: (f64, f64),
+    /// Scrolling that hasn't added up to a whole step yet, as trackpads scroll in fractions
+    wheel: (f64, f64),
+    /// The key command being held and when it was first pressed
+    held: Option<(Command, Instant)>,
+}
 
-    // Don't handle movement input when modal is showing
-    if state.show_modal {
-        return true;
+impl Input {
+    pub fn new(keymap: Keymap, window_size: (i32, i32)) -> Self {
+        Self {
+            keymap,
+            cursor: (0., 0.),
+            window_size: (window_size.0 as f64, window_size.1 as f64),
+            wheel: (0., 0.),
+            held: None,
+        }
     }
 
-    match event {
-        WindowEvent::Key(Key::Enter, _, Action::Press, _) => state.show_modal = true,
-        WindowEvent::Key(Key::Right, _, Action::Press | Action::Repeat, _) => {
-            let new = state.selected_card.0.saturating_add(1);
-            if new < state.rows[state.selected_card.1].cards.len() {
-                state.selected_card.0 = new;
+    pub async fn handle_event(&mut self, event: WindowEvent, state: AsyncState) -> bool {
+        let (command, action) = match event {
+            WindowEvent::Close => return false,
+            WindowEvent::Key(key, _, action, mods) => {
+                let command = match self.keymap.command(key, mods) {
+                    Some(command) => command,
+                    None => return true,
+                };
+                match action {
+                    KeyAction::Press => self.press(command, Instant::now()),
+                    KeyAction::Release => self.release(command),
+                    KeyAction::Repeat => {
+                        let held_for = self.held_for(command, Instant::now());
+                        for _ in 0..repeat_steps(command, held_for) {
+                            if !handle_command(command, action, state.clone()).await {
+                                return false;
+                            }
+                        }
+                        return true;
+                    }
+                }
+                (command, action)
+            }
+            WindowEvent::Size(width, height) => {
+                self.window_size = (width as f64, height as f64);
+                return true;
+            }
+            WindowEvent::CursorPos(x, y) => {
+                self.cursor = (x, y);
+                // Hovering focuses whatever card is under the cursor
+                let card = {
+                    let state = state.read().await;
+                    self.card_under_cursor(&state)
+                        .filter(|&card| card != state.selected_card)
+                };
+                if let Some(card) = card {
+                    action::dispatch(&state, Action::Focus(card)).await;
+                }
+                return true;
             }
+            WindowEvent::MouseButton(MouseButton::Button1, KeyAction::Press, _) => {
+                let card = {
+                    let state = state.read().await;
+                    self.card_under_cursor(&state)
+                        .filter(|_| matches!(state.page(), Page::Home | Page::Collection(_)))
+                };
+                if let Some(card) = card {
+                    action::dispatch(&state, Action::Focus(card)).await;
+                    action::dispatch(&state, Action::OpenDetails).await;
+                }
+                return true;
+            }
+            // Right click and the mouse's back button
+            WindowEvent::MouseButton(MouseButton::Button2 | MouseButton::Button4, action, _) => {
+                (Command::Back, action)
+            }
+            WindowEvent::Scroll(x, y) => {
+                self.wheel.0 += x;
+                self.wheel.1 += y;
+                for command in self.wheel_steps() {
+                    if !handle_command(command, KeyAction::Press, state.clone()).await {
+                        return false;
+                    }
+                }
+                return true;
+            }
+            _ => return true,
+        };
+        handle_command(command, action, state).await
+    }
+
+    /// Start timing a command that repeats while its key is held.
+    /// Other keys pressed meanwhile don't interrupt it.
+    fn press(&mut self, command: Command, now: Instant) {
+        if repeats(command) {
+            self.held = Some((command, now));
         }
-        WindowEvent::Key(Key::Left, _, Action::Press | Action::Repeat, _) => {
-            state.selected_card.0 = state.selected_card.0.saturating_sub(1);
+    }
+
+    fn release(&mut self, command: Command) {
+        if self.held.map(|(held, _)| held) == Some(command) {
+            self.held = None;
         }
-        WindowEvent::Key(Key::Up, _, Action::Press | Action::Repeat, _) => {
-            let new = state.selected_card.1.saturating_sub(1);
-            if new < state.rows.len() {
-                let scroll_old = state.rows[state.selected_card.1].scroll.round() as isize;
-                let scroll_new = state.rows[new].scroll.round() as isize;
-                let mut new_scrl = state.selected_card.0 as isize;
-                new_scrl += scroll_new - scroll_old;
-                state.selected_card.0 = new_scrl as usize;
-                state.selected_card.1 = new;
-            }
+    }
+
+    /// Seconds `command` has been held for, or 0 if it isn't the one being held
+    fn held_for(&self, command: Command, now: Instant) -> f32 {
+        match self.held {
+            Some((held, since)) if held == command => (now - since).as_secs_f32(),
+            _ => 0.,
         }
-        WindowEvent::Key(Key::Down, _, Action::Press | Action::Repeat, _) => {
-            let new = state.selected_card.1.saturating_add(1);
-            if new < state.rows.len() {
-                let scroll_old = state.rows[state.selected_card.1].scroll.round() as isize;
-                let scroll_new = state.rows[new].scroll.round() as isize;
-                let mut new_scrl = state.selected_card.0 as isize;
-                new_scrl += scroll_new - scroll_old;
-                state.selected_card.0 = new_scrl as usize;
-                state.selected_card.1 = new;
+    }
+
+    /// The card under the cursor, topmost first
+    fn card_under_cursor(&self, state: &State) -> Option<(usize, usize)> {
+        let (width, height) = self.window_size;
+        let x = (self.cursor.0 / width.max(1.) * 2. - 1.) as f32;
+        let y = (1. - self.cursor.1 / height.max(1.) * 2.) as f32;
+        state
+            .card_rects
+            .iter()
+            .rev()
+            .find(|(_, [left, bottom, right, top])| {
+                (*left..*right).contains(&x) && (*bottom..*top).contains(&y)
+            })
+            .map(|(card, _)| *card)
+    }
+
+    /// Take whole steps out of the accumulated scrolling, one move per step
+    fn wheel_steps(&mut self) -> Vec<Command> {
+        let mut steps = Vec::new();
+        let axes = [
+            (&mut self.wheel.0, Command::MoveRight, Command::MoveLeft),
+            (&mut self.wheel.1, Command::MoveUp, Command::MoveDown),
+        ];
+        for (amount, positive, negative) in axes {
+            while *amount >= 1. {
+                *amount -= 1.;
+                steps.push(positive);
+            }
+            while *amount <= -1. {
+                *amount += 1.;
+                steps.push(negative);
             }
         }
-        _ => (),
+        steps
     }
+}
+
+/// Whether holding down `command` repeats it
+pub fn repeats(command: Command) -> bool {
+    matches!(
+        command,
+        Command::MoveUp
+            | Command::MoveDown
+            | Command::MoveLeft
+            | Command::MoveRight
+            | Command::PageUp
+            | Command::PageDown
+    )
+}
+
+/// How many times a repeat of `command` should be applied once it's been held for
+/// `held_for` seconds. Rows can be long, so moving along them speeds up.
+pub fn repeat_steps(command: Command, held_for: f32) -> usize {
+    match command {
+        Command::MoveLeft | Command::MoveRight => {
+            (1 + (held_for / REPEAT_ACCELERATE_AFTER) as usize).min(REPEAT_MAX_STEPS)
+        }
+        _ => 1,
+    }
+}
+
+/// Apply a command from any input device. Returns false when it's time to quit.
+pub async fn handle_command(command: Command, key_action: KeyAction, state: AsyncState) -> bool {
+    let action = match (command, key_action) {
+        (Command::Quit, KeyAction::Release) => return false,
+        (Command::Back, KeyAction::Press) => Action::Back,
+        (Command::Select, KeyAction::Press) => Action::OpenDetails,
+        (Command::OpenRow, KeyAction::Press) => Action::OpenCollection,
+        (Command::Search, KeyAction::Press) => Action::OpenPage(Page::Search),
+        (Command::Settings, KeyAction::Press) => Action::OpenPage(Page::Settings),
+        (_, KeyAction::Release) => return true,
+        (Command::MoveRight, _) => Action::MoveAcross(1),
+        (Command::MoveLeft, _) => Action::MoveAcross(-1),
+        (Command::MoveUp, _) => Action::MoveRows(-1),
+        (Command::MoveDown, _) => Action::MoveRows(1),
+        (Command::PageUp, _) => Action::MoveRows(-(PAGE_ROWS as isize)),
+        (Command::PageDown, _) => Action::MoveRows(PAGE_ROWS as isize),
+        (Command::Home, _) => Action::JumpAcross(0),
+        (Command::End, _) => Action::JumpAcross(usize::MAX),
+        (Command::Top, KeyAction::Press) => Action::Top,
+        _ => return true,
+    };
+    action::dispatch(&state, action).await;
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input over a window twice as wide as it is tall, with the cursor at `cursor`
+    fn input(cursor: (f64, f64)) -> Input {
+        let mut input = Input::new(Keymap::default(), (1000, 500));
+        input.cursor = cursor;
+        input
+    }
+
+    fn state(card_rects: Vec<((usize, usize), [f32; 4])>) -> State {
+        let mut state = State::new();
+        state.card_rects = card_rects;
+        state
+    }
+
+    #[test]
+    fn topmost_card_wins() {
+        let state = state(vec![
+            ((0, 0), [-1., -1., 0.5, 0.5]),
+            ((1, 0), [0., 0., 1., 1.]),
+        ]);
+        // Both rects hold the cursor, and the later one was drawn over the earlier
+        assert_eq!(input((600., 200.)).card_under_cursor(&state), Some((1, 0)));
+        assert_eq!(input((250., 400.)).card_under_cursor(&state), Some((0, 0)));
+        assert_eq!(input((1000., 0.)).card_under_cursor(&state), None);
+    }
+
+    #[test]
+    fn edges_follow_the_window_size() {
+        let state = state(vec![((2, 1), [0., 0., 0.5, 0.5])]);
+        // The left and bottom edges are inside, the right and top ones aren't
+        assert_eq!(input((500., 250.)).card_under_cursor(&state), Some((2, 1)));
+        assert_eq!(input((750., 250.)).card_under_cursor(&state), None);
+        assert_eq!(input((500., 125.)).card_under_cursor(&state), None);
+
+        // After a resize the same cursor position lands somewhere else
+        let mut input = input((600., 200.));
+        assert_eq!(input.card_under_cursor(&state), Some((2, 1)));
+        input.window_size = (2000., 1000.);
+        assert_eq!(input.card_under_cursor(&state), None);
+    }
+
+    #[test]
+    fn wheel_adds_up_fractions() {
+        let mut input = input((0., 0.));
+        input.wheel.1 -= 0.4;
+        assert!(input.wheel_steps().is_empty());
+        input.wheel.1 -= 0.4;
+        assert!(input.wheel_steps().is_empty());
+        input.wheel.1 -= 0.4;
+        assert_eq!(input.wheel_steps(), [Command::MoveDown]);
+        assert!((input.wheel.1 + 0.2).abs() < 1e-9);
+
+        input.wheel = (2.5, 1.2);
+        assert_eq!(
+            input.wheel_steps(),
+            [Command::MoveRight, Command::MoveRight, Command::MoveUp]
+        );
+        assert!((input.wheel.0 - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn moves_along_rows_accelerate() {
+        assert_eq!(repeat_steps(Command::MoveRight, 0.), 1);
+        assert_eq!(repeat_steps(Command::MoveLeft, REPEAT_ACCELERATE_AFTER), 2);
+        assert_eq!(repeat_steps(Command::MoveRight, 60.), REPEAT_MAX_STEPS);
+        assert_eq!(repeat_steps(Command::MoveDown, 60.), 1);
+        assert_eq!(repeat_steps(Command::PageDown, 60.), 1);
+    }
+
+    #[test]
+    fn other_keys_dont_interrupt_a_held_move() {
+        let mut input = input((0., 0.));
+        let start = Instant::now();
+        let later = |seconds: f32| start + std::time::Duration::from_secs_f32(seconds);
+        input.press(Command::MoveRight, start);
+        // Tapping Enter neither replaces nor clears the held move
+        input.press(Command::Select, later(1.));
+        input.release(Command::Select);
+        assert_eq!(input.held_for(Command::MoveRight, later(2.)), 2.);
+
+        // Another move takes over, and letting go of the first doesn't stop it
+        input.press(Command::MoveLeft, later(2.));
+        input.release(Command::MoveRight);
+        assert_eq!(input.held_for(Command::MoveRight, later(3.)), 0.);
+        assert_eq!(input.held_for(Command::MoveLeft, later(3.)), 1.);
+        input.release(Command::MoveLeft);
+        assert_eq!(input.held_for(Command::MoveLeft, later(3.)), 0.);
+    }
+}
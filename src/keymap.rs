@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use glfw::{Key, Modifiers};
+use serde::Deserialize;
+
+/// Where key bindings are read from. The defaults below are used without it.
+const KEYMAP_PATH: &str = "config/keymap.json";
+
+/// Bindings used when there's no keymap on disk
+const DEFAULT_BINDINGS: [(&str, Command); 17] = [
+    ("Up", Command::MoveUp),
+    ("Down", Command::MoveDown),
+    ("Left", Command::MoveLeft),
+    ("Right", Command::MoveRight),
+    ("Enter", Command::Select),
+    ("KpEnter", Command::Select),
+    ("Backspace", Command::Back),
+    ("Escape", Command::Quit),
+    ("Ctrl+F", Command::Search),
+    ("Slash", Command::Search),
+    ("PageUp", Command::PageUp),
+    ("PageDown", Command::PageDown),
+    ("Home", Command::Home),
+    ("End", Command::End),
+    ("Ctrl+Home", Command::Top),
+    ("Shift+Enter", Command::OpenRow),
+    ("Ctrl+Comma", Command::Settings),
+];
+
+/// What a key press asks the app to do, independent of which key it was
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum Command {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Select,
+    Back,
+    Quit,
+    Search,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    /// Back to the first card of the first row
+    Top,
+    /// Open the focused row on a page of its own
+    OpenRow,
+    Settings,
+}
+
+/// Maps keys and modifiers to commands
+pub struct Keymap {
+    bindings: HashMap<(Key, Modifiers), Command>,
+}
+
+impl Keymap {
+    /// Read the keymap from disk, falling back to the defaults if it's missing or invalid.
+    /// The file is a JSON object from bindings like `"Ctrl+F"` to command names.
+    pub fn load() -> Self {
+        let bindings = std::fs::read_to_string(KEYMAP_PATH)
+            .map_err(|e| println!("error while reading {}: {}", KEYMAP_PATH, e))
+            .ok()
+            .and_then(|json| {
+                serde_json::from_str::<HashMap<String, Command>>(&json)
+                    .map_err(|e| println!("error while parsing {}: {}", KEYMAP_PATH, e))
+                    .ok()
+            });
+        match bindings {
+            Some(bindings) => Self::from_bindings(bindings.iter().map(|(b, c)| (b.as_str(), *c))),
+            None => Self::default(),
+        }
+    }
+
+    fn from_bindings<'a>(bindings: impl Iterator<Item = (&'a str, Command)>) -> Self {
+        let bindings = bindings
+            .filter_map(|(binding, command)| {
+                parse_binding(binding)
+                    .map_err(|e| println!("error while parsing key binding {:?}: {}", binding, e))
+                    .ok()
+                    .map(|parsed| (parsed, command))
+            })
+            .collect();
+        Self { bindings }
+    }
+
+    /// The command bound to a key, ignoring lock modifiers
+    pub fn command(&self, key: Key, mods: Modifiers) -> Option<Command> {
+        let mods =
+            mods & (Modifiers::Shift | Modifiers::Control | Modifiers::Alt | Modifiers::Super);
+        self.bindings.get(&(key, mods)).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_bindings(DEFAULT_BINDINGS.iter().copied())
+    }
+}
+
+/// Split a binding like `Ctrl+Shift+Tab` into its key and modifiers.
+/// Keys are named as glfw debug-prints them, e.g. `Up`, `A` or `KpEnter`.
+fn parse_binding(binding: &str) -> Result<(Key, Modifiers), String> {
+    let mut parts: Vec<&str> = binding.split('+').map(str::trim).collect();
+    let name = parts.pop().unwrap_or_default();
+    let key = parse_key(name).ok_or_else(|| format!("unknown key {:?}", name))?;
+    let mut mods = Modifiers::empty();
+    for part in parts {
+        mods |= match part.to_ascii_lowercase().as_str() {
+            "shift" => Modifiers::Shift,
+            "ctrl" | "control" => Modifiers::Control,
+            "alt" | "option" => Modifiers::Alt,
+            "super" | "cmd" | "command" => Modifiers::Super,
+            _ => return Err(format!("unknown modifier {:?}", part)),
+        };
+    }
+    Ok((key, mods))
+}
+
+/// A key by the name glfw debug-prints for it
+fn parse_key(name: &str) -> Option<Key> {
+    Some(match name {
+        "Space" => Key::Space,
+        "Apostrophe" => Key::Apostrophe,
+        "Comma" => Key::Comma,
+        "Minus" => Key::Minus,
+        "Period" => Key::Period,
+        "Slash" => Key::Slash,
+        "Num0" => Key::Num0,
+        "Num1" => Key::Num1,
+        "Num2" => Key::Num2,
+        "Num3" => Key::Num3,
+        "Num4" => Key::Num4,
+        "Num5" => Key::Num5,
+        "Num6" => Key::Num6,
+        "Num7" => Key::Num7,
+        "Num8" => Key::Num8,
+        "Num9" => Key::Num9,
+        "Semicolon" => Key::Semicolon,
+        "Equal" => Key::Equal,
+        "A" => Key::A,
+        "B" => Key::B,
+        "C" => Key::C,
+        "D" => Key::D,
+        "E" => Key::E,
+        "F" => Key::F,
+        "G" => Key::G,
+        "H" => Key::H,
+        "I" => Key::I,
+        "J" => Key::J,
+        "K" => Key::K,
+        "L" => Key::L,
+        "M" => Key::M,
+        "N" => Key::N,
+        "O" => Key::O,
+        "P" => Key::P,
+        "Q" => Key::Q,
+        "R" => Key::R,
+        "S" => Key::S,
+        "T" => Key::T,
+        "U" => Key::U,
+        "V" => Key::V,
+        "W" => Key::W,
+        "X" => Key::X,
+        "Y" => Key::Y,
+        "Z" => Key::Z,
+        "LeftBracket" => Key::LeftBracket,
+        "Backslash" => Key::Backslash,
+        "RightBracket" => Key::RightBracket,
+        "GraveAccent" => Key::GraveAccent,
+        "World1" => Key::World1,
+        "World2" => Key::World2,
+        "Escape" => Key::Escape,
+        "Enter" => Key::Enter,
+        "Tab" => Key::Tab,
+        "Backspace" => Key::Backspace,
+        "Insert" => Key::Insert,
+        "Delete" => Key::Delete,
+        "Right" => Key::Right,
+        "Left" => Key::Left,
+        "Down" => Key::Down,
+        "Up" => Key::Up,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "CapsLock" => Key::CapsLock,
+        "ScrollLock" => Key::ScrollLock,
+        "NumLock" => Key::NumLock,
+        "PrintScreen" => Key::PrintScreen,
+        "Pause" => Key::Pause,
+        "F1" => Key::F1,
+        "F2" => Key::F2,
+        "F3" => Key::F3,
+        "F4" => Key::F4,
+        "F5" => Key::F5,
+        "F6" => Key::F6,
+        "F7" => Key::F7,
+        "F8" => Key::F8,
+        "F9" => Key::F9,
+        "F10" => Key::F10,
+        "F11" => Key::F11,
+        "F12" => Key::F12,
+        "F13" => Key::F13,
+        "F14" => Key::F14,
+        "F15" => Key::F15,
+        "F16" => Key::F16,
+        "F17" => Key::F17,
+        "F18" => Key::F18,
+        "F19" => Key::F19,
+        "F20" => Key::F20,
+        "F21" => Key::F21,
+        "F22" => Key::F22,
+        "F23" => Key::F23,
+        "F24" => Key::F24,
+        "F25" => Key::F25,
+        "Kp0" => Key::Kp0,
+        "Kp1" => Key::Kp1,
+        "Kp2" => Key::Kp2,
+        "Kp3" => Key::Kp3,
+        "Kp4" => Key::Kp4,
+        "Kp5" => Key::Kp5,
+        "Kp6" => Key::Kp6,
+        "Kp7" => Key::Kp7,
+        "Kp8" => Key::Kp8,
+        "Kp9" => Key::Kp9,
+        "KpDecimal" => Key::KpDecimal,
+        "KpDivide" => Key::KpDivide,
+        "KpMultiply" => Key::KpMultiply,
+        "KpSubtract" => Key::KpSubtract,
+        "KpAdd" => Key::KpAdd,
+        "KpEnter" => Key::KpEnter,
+        "KpEqual" => Key::KpEqual,
+        "LeftShift" => Key::LeftShift,
+        "LeftControl" => Key::LeftControl,
+        "LeftAlt" => Key::LeftAlt,
+        "LeftSuper" => Key::LeftSuper,
+        "RightShift" => Key::RightShift,
+        "RightControl" => Key::RightControl,
+        "RightAlt" => Key::RightAlt,
+        "RightSuper" => Key::RightSuper,
+        "Menu" => Key::Menu,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_and_modifiers() {
+        assert_eq!(parse_binding("Up"), Ok((Key::Up, Modifiers::empty())));
+        assert_eq!(
+            parse_binding("Ctrl + Shift+KpEnter"),
+            Ok((Key::KpEnter, Modifiers::Control | Modifiers::Shift))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert!(parse_binding("Ctrl+Upp").is_err());
+        assert!(parse_binding("Hyper+A").is_err());
+        assert!(parse_binding("Ctrl+").is_err());
+        assert!(parse_binding("").is_err());
+    }
+
+    #[test]
+    fn defaults_all_parse() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.bindings.len(), DEFAULT_BINDINGS.len());
+        assert_eq!(
+            keymap.command(Key::Home, Modifiers::Control | Modifiers::NumLock),
+            Some(Command::Top)
+        );
+    }
+}
@@ -1,17 +1,23 @@
 use glfw::Context as _;
-use glyph_brush::{ab_glyph::FontArc, GlyphBrush, GlyphBrushBuilder};
+use glyph_brush::{GlyphBrush, GlyphBrushBuilder};
 use luminance::{context::GraphicsContext, pipeline::PipelineState};
 use luminance_glfw::GlfwSurface;
 use luminance_windowing::{WindowDim, WindowOpt};
 use std::process::exit;
-use std::sync::atomic::AtomicU32;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::RwLock;
 
+mod action;
+mod anim;
 mod api;
+mod cache;
+mod focus;
+mod gamepad;
 mod input;
+mod keymap;
 mod model;
+mod prefetch;
 mod state;
 mod tex;
 mod text;
@@ -21,12 +27,32 @@ mod vertex;
 use state::*;
 use vertex::*;
 
-use crate::{tex::TextureHost, text::TextRenderer, tile::TileRenderer};
+use crate::{
+    action::Action,
+    api::ImageLoader,
+    cache::ImageCache,
+    gamepad::Gamepad,
+    input::Input,
+    keymap::Keymap,
+    prefetch::Prefetch,
+    tex::{TextureHost, TextureRegistry},
+    text::{TextEffect, TextRenderer},
+    tile::TileRenderer,
+};
 
 // TODO: Use dynamic width and height
 pub const WIDTH: u32 = 960;
 pub const HEIGHT: u32 = 540;
 
+/// Bytes of GPU memory the texture atlas may use before its least recently drawn pages are evicted
+pub const TEXTURE_BUDGET: usize = 256 * 1024 * 1024;
+/// Bytes of encoded images kept in memory so evicted textures reload without the network
+pub const IMAGE_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+/// Rows past the focused one whose sets are fetched ahead of time, and above and below it whose artwork is loaded
+pub const PREFETCH_ROWS: usize = 2;
+/// Cards either side of the focused one in each row whose artwork is loaded
+pub const PREFETCH_CARDS: usize = 6;
+
 #[tokio::main]
 async fn main() {
     // our graphics surface
@@ -62,25 +88,37 @@ async fn main_loop(surface: GlfwSurface) {
 
     let state = Arc::new(RwLock::new(State::new()));
 
-    let font = FontArc::try_from_slice(include_bytes!("OpenSans-Regular.ttf")).expect("font");
-    let mut glyph_brush: GlyphBrush<TextInstance> = GlyphBrushBuilder::using_font(font).build();
+    let mut glyph_brush: GlyphBrush<TextInstance> =
+        GlyphBrushBuilder::using_fonts(text::load_fonts()).build();
 
-    let mut text_renderer = TextRenderer::new(&mut ctxt, &mut glyph_brush);
+    let mut text_renderer = TextRenderer::new(&mut ctxt, &mut glyph_brush, TextEffect::from_env());
     let mut tile_renderer = TileRenderer::new(&mut ctxt);
-    let mut texture_host = TextureHost::new();
-
-    let tex_uid = AtomicU32::new(1u32);
-
-    tokio::spawn(api::load_home(
-        state.clone(),
-        texture_host.queued_images.clone(),
-    ));
+    let mut texture_host = TextureHost::new(TEXTURE_BUDGET);
+    let image_loader = ImageLoader {
+        state: state.clone(),
+        textures: TextureRegistry::default(),
+        queued_images: texture_host.queued_images.clone(),
+        image_cache: Arc::new(RwLock::new(ImageCache::new(IMAGE_CACHE_BUDGET))),
+        client: reqwest::Client::new(),
+    };
+    // Uploaded with the first frame, before any tile needs them
+    texture_host
+        .queued_images
+        .write()
+        .await
+        .extend(image_loader.textures.built_in_images());
+
+    let mut prefetch = Prefetch::new(PREFETCH_ROWS, PREFETCH_CARDS);
+    let mut input = Input::new(Keymap::load(), ctxt.window.get_size());
+    let mut gamepad = Gamepad::default();
+
+    tokio::spawn(api::load_home(state.clone()));
 
     'app: loop {
         // Handle events
         ctxt.window.glfw.poll_events();
         for (_, event) in glfw::flush_messages(&events) {
-            if !input::handle_event(event, state.clone()).await {
+            if !input.handle_event(event, state.clone()).await {
                 break 'app;
             }
         }
@@ -90,17 +128,27 @@ async fn main_loop(surface: GlfwSurface) {
         let delta_t = t - last_t;
         last_t = t;
 
+        // Gamepads are polled rather than sending events
+        let pad = gamepad::poll(&ctxt.window.glfw).unwrap_or_default();
+        for (command, action) in gamepad.update(pad, delta_t) {
+            if !input::handle_command(command, action, state.clone()).await {
+                break 'app;
+            }
+        }
+
+        // Start downloads for wherever focus has moved to
+        let framebuffer_height = ctxt.window.get_framebuffer_size().1 as f32;
+        prefetch
+            .update(state.clone(), &image_loader, framebuffer_height)
+            .await;
+
         // Process what's to be rendered
-        texture_host.process_queued(&mut ctxt).await;
+        action::dispatch(&state, Action::Tick(delta_t)).await;
+        texture_host.process_queued(&mut ctxt, state.clone()).await;
         tile_renderer
-            .update_tiles(
-                delta_t,
-                state.clone(),
-                &mut glyph_brush,
-                &tex_uid,
-                texture_host.queued_images.clone(),
-            )
+            .update_tiles(delta_t, state.clone(), &mut glyph_brush)
             .await;
+        tile_renderer.prepare(&mut ctxt, &mut texture_host);
         text_renderer.process_queued(&mut ctxt, &mut glyph_brush);
 
         // Render pipeline
@@ -110,11 +158,7 @@ async fn main_loop(surface: GlfwSurface) {
                 &back_buffer,
                 &PipelineState::default().set_clear_color([0.01, 0.01, 0.01, 1.]),
                 |pipeline, mut shd_gate| {
-                    tile_renderer.render(
-                        &pipeline,
-                        &mut shd_gate,
-                        &mut texture_host.bindable_textures,
-                    )?;
+                    tile_renderer.render(&pipeline, &mut shd_gate, &mut texture_host)?;
                     text_renderer.render(&pipeline, &mut shd_gate)?;
                     Ok(())
                 },
@@ -87,6 +87,7 @@ pub struct Item {
 #[derive(Debug, Deserialize)]
 pub struct ItemText {
     pub title: ItemTextTitle,
+    pub description: Option<ItemTextDescription>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -94,6 +95,12 @@ pub struct ItemTextTitle {
     pub full: ItemTextTitleFull,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ItemTextDescription {
+    pub full: Option<ItemTextTitleFull>,
+    pub brief: Option<ItemTextTitleFull>,
+}
+
 #[derive(Debug, Deserialize)]
 pub enum ItemTextTitleFull {
     #[serde(rename = "series")]
@@ -123,6 +130,13 @@ impl ItemText {
     pub fn get_name(&self) -> Option<String> {
         Some(self.title.full.get_default().content.clone())
     }
+
+    /// The longest description available
+    pub fn get_description(&self) -> Option<String> {
+        let description = self.description.as_ref()?;
+        let text = description.full.as_ref().or(description.brief.as_ref())?;
+        Some(text.get_default().content.clone())
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -140,8 +154,12 @@ pub struct Release {
 #[derive(Debug, Deserialize)]
 pub struct Image {
     pub tile: Option<TileImageWrapper>,
+    pub title_treatment: Option<TileImageWrapper>,
 }
 
+/// Aspect ratio of the tile artwork variant we request
+pub const TILE_ASPECT: f32 = 1.78;
+
 #[derive(Debug, Deserialize)]
 pub struct TileImageWrapper {
     #[serde(rename = "1.78")]
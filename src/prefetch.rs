@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+
+use crate::action::{self, Action};
+use crate::api::{self, ImageLoader};
+use crate::state::{AsyncState, CardImage, ImageSlot, Page, State, COLLECTION_COLUMNS};
+use crate::tile::{LOGO_ASPECT, LOGO_SIZE, SELECTED_TILE_SIZE};
+
+/// What prefetching depends on. It only runs again once one of these changes.
+#[derive(Clone, Copy, PartialEq)]
+struct Trigger {
+    focus: (usize, usize),
+    page: Page,
+    rows: usize,
+    queued_rows: usize,
+    is_loading_row: bool,
+    image_generation: usize,
+    /// Image sizes are picked for it, so a resize loads sharper ones
+    framebuffer_height: f32,
+}
+
+impl Trigger {
+    fn of(state: &State, framebuffer_height: f32) -> Self {
+        Self {
+            focus: state.selected_card,
+            page: state.page(),
+            rows: state.rows.len(),
+            queued_rows: state.queued_rows.len(),
+            is_loading_row: state.is_loading_row,
+            image_generation: state.image_generation,
+            framebuffer_height,
+        }
+    }
+}
+
+/// What to download for a state
+struct Plan {
+    /// A queued set to start fetching
+    next_row: Option<String>,
+    /// Card, which of its images, where from, and the width in physical pixels to load it at
+    images: Vec<((usize, usize), ImageSlot, String, f32)>,
+    /// Cards now in range, unless the page doesn't change which are
+    in_range: Option<HashSet<(usize, usize)>>,
+}
+
+/// Decides which sets and artwork to download as focus moves around
+pub struct Prefetch {
+    /// How many rows past the focused one sets are fetched, and artwork is loaded above and below
+    rows: usize,
+    /// How many cards either side of where a row is focused artwork is loaded
+    cards: usize,
+    /// Height of the framebuffer in physical pixels as of the last update, for picking image sizes
+    framebuffer_height: f32,
+    last: Option<Trigger>,
+    /// Cards in range last time, so failed images are retried as they come back into range
+    in_range: HashSet<(usize, usize)>,
+}
+
+impl Prefetch {
+    pub fn new(rows: usize, cards: usize) -> Self {
+        Self {
+            rows,
+            cards,
+            framebuffer_height: 0.,
+            last: None,
+            in_range: HashSet::new(),
+        }
+    }
+
+    /// Physical width in pixels of a tile drawn at `size` with the given aspect ratio
+    fn pixel_width(&self, size: f32, aspect: f32) -> f32 {
+        size * 0.5 * aspect * self.framebuffer_height
+    }
+
+    /// Where to download a card's artwork from, if it's missing or failed before it came
+    /// into range
+    fn wanted<'a>(&self, card: (usize, usize), image: &'a CardImage) -> Option<&'a String> {
+        match image {
+            CardImage::URI(uri) => Some(uri),
+            CardImage::Failure(uri) if !self.in_range.contains(&card) => Some(uri),
+            _ => None,
+        }
+    }
+
+    /// Request whatever's newly needed around the focus, for a framebuffer `framebuffer_height`
+    /// physical pixels tall
+    pub async fn update(
+        &mut self,
+        state: AsyncState,
+        loader: &ImageLoader,
+        framebuffer_height: f32,
+    ) {
+        // Most frames nothing's changed, so only look under a read lock
+        if self.last == Some(Trigger::of(&*state.read().await, framebuffer_height)) {
+            return;
+        }
+        let state_ = state.clone();
+        let mut state = state.write().await;
+        // Something may have changed again before the write lock was free
+        let trigger = Trigger::of(&state, framebuffer_height);
+        if self.last == Some(trigger) {
+            return;
+        }
+        self.last = Some(trigger);
+        self.framebuffer_height = framebuffer_height;
+
+        let plan = self.plan(&state);
+        if let Some(in_range) = plan.in_range {
+            self.in_range = in_range;
+        }
+        if let Some(ref_id) = plan.next_row {
+            action::apply(&mut state, Action::RowLoadStarted(ref_id.clone()));
+            tokio::spawn(api::load_next_row(state_, ref_id));
+        }
+        for (card, slot, uri, pixel_width) in plan.images {
+            let handle = loader.load(&uri, card, slot, pixel_width);
+            action::apply(&mut state, Action::ImageRequested { card, slot, handle });
+        }
+    }
+
+    /// Work out what to download for the state as it is now, without starting anything
+    fn plan(&self, state: &State) -> Plan {
+        let mut plan = Plan {
+            next_row: None,
+            images: Vec::new(),
+            in_range: None,
+        };
+        match state.page() {
+            Page::Home => {
+                let (fx, fy) = state.selected_card;
+
+                // Fetch sets before they're scrolled to, one at a time
+                if fy + self.rows >= state.rows.len() && !state.is_loading_row {
+                    plan.next_row = state.queued_rows.last().cloned();
+                }
+
+                let mut in_range = HashSet::new();
+                for y in fy.saturating_sub(self.rows)..=fy + self.rows {
+                    let row = match state.rows.get(y) {
+                        Some(row) => row,
+                        None => continue,
+                    };
+                    // Rows other than the focused one are loaded around the card they'd focus
+                    let center = if y == fy {
+                        fx
+                    } else {
+                        row.focused
+                            .unwrap_or_else(|| row.scroll.target().max(0.) as usize)
+                    };
+                    // Sized for the selected card, the largest a tile gets in the grid
+                    let pixel_width = self.pixel_width(SELECTED_TILE_SIZE, row.aspect);
+                    for x in center.saturating_sub(self.cards)..=center + self.cards {
+                        let card = match row.cards.get(x) {
+                            Some(card) => card,
+                            None => break,
+                        };
+                        in_range.insert((x, y));
+                        if let Some(uri) = self.wanted((x, y), &card.image) {
+                            plan.images
+                                .push(((x, y), ImageSlot::Tile, uri.clone(), pixel_width));
+                        }
+                    }
+                }
+                plan.in_range = Some(in_range);
+            }
+            Page::Collection(y) => {
+                // Lines of the grid around the focused one
+                let line = state.selected_card.0 / COLLECTION_COLUMNS;
+                let first = line.saturating_sub(self.rows) * COLLECTION_COLUMNS;
+                let end = (line + self.rows + 1) * COLLECTION_COLUMNS;
+                let mut in_range = HashSet::new();
+                if let Some(row) = state.rows.get(y) {
+                    let pixel_width = self.pixel_width(SELECTED_TILE_SIZE, row.aspect);
+                    for (x, card) in row.cards.iter().enumerate().take(end).skip(first) {
+                        in_range.insert((x, y));
+                        if let Some(uri) = self.wanted((x, y), &card.image) {
+                            plan.images
+                                .push(((x, y), ImageSlot::Tile, uri.clone(), pixel_width));
+                        }
+                    }
+                }
+                plan.in_range = Some(in_range);
+            }
+            Page::Details((x, y)) => {
+                let logo = state
+                    .rows
+                    .get(y)
+                    .and_then(|row| row.cards.get(x))
+                    .and_then(|card| card.logo.as_ref());
+                if let Some(CardImage::URI(uri)) = logo {
+                    let pixel_width = self.pixel_width(LOGO_SIZE, LOGO_ASPECT);
+                    plan.images
+                        .push(((x, y), ImageSlot::Logo, uri.clone(), pixel_width));
+                }
+            }
+            Page::Search | Page::Settings => {}
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anim::Anim;
+    use crate::model::collection::TILE_ASPECT;
+    use crate::state::{
+        Card, Row, CARD_FADE_MOTION, CARD_SIZE_MOTION, ROW_SCROLL_MOTION, TEXT_HEIGHT_MOTION,
+    };
+
+    /// A home page with rows of the given lengths, none of their artwork loaded yet
+    fn state(rows: &[usize]) -> State {
+        let mut state = State::new();
+        for (y, &len) in rows.iter().enumerate() {
+            let cards = (0..len)
+                .map(|x| Card {
+                    title: format!("{}, {}", x, y),
+                    image: CardImage::URI(format!("https://example.com/{}/{}.jpg", x, y)),
+                    logo: None,
+                    size: Anim::new(0., CARD_SIZE_MOTION),
+                    fade: Anim::new(0., CARD_FADE_MOTION),
+                    description: None,
+                    ratings: Vec::new(),
+                    releases: Vec::new(),
+                })
+                .collect();
+            state.rows.push(Row {
+                scroll: Anim::new(0., ROW_SCROLL_MOTION),
+                text_height: Anim::new(0., TEXT_HEIGHT_MOTION),
+                aspect: TILE_ASPECT,
+                title: format!("Row {}", y),
+                cards,
+                focused: None,
+            });
+        }
+        state
+    }
+
+    fn in_range(plan: &Plan) -> Vec<(usize, usize)> {
+        let mut cards: Vec<_> = plan.in_range.iter().flatten().copied().collect();
+        cards.sort_by_key(|&(x, y)| (y, x));
+        cards
+    }
+
+    fn requested(plan: &Plan) -> Vec<(usize, usize)> {
+        plan.images.iter().map(|image| image.0).collect()
+    }
+
+    #[test]
+    fn loads_around_where_each_row_would_focus() {
+        let prefetch = Prefetch::new(1, 1);
+        let mut state = state(&[8, 8, 8, 8]);
+        state.selected_card = (5, 1);
+        // The row above comes back to where it was left, the one below to where it's scrolled
+        state.rows[0].focused = Some(0);
+        state.rows[2].scroll.set_target(3.);
+
+        let plan = prefetch.plan(&state);
+        let expected = [
+            (0, 0),
+            (1, 0),
+            (4, 1),
+            (5, 1),
+            (6, 1),
+            (2, 2),
+            (3, 2),
+            (4, 2),
+        ];
+        assert_eq!(in_range(&plan), expected);
+        let mut requested = requested(&plan);
+        requested.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(requested, expected);
+        assert_eq!(plan.next_row, None);
+    }
+
+    #[test]
+    fn queued_sets_load_as_focus_nears_the_end() {
+        let prefetch = Prefetch::new(1, 1);
+        let mut state = state(&[8, 8, 8]);
+        state.queued_rows = vec!["later".to_owned(), "next".to_owned()];
+        state.selected_card = (0, 1);
+        assert_eq!(prefetch.plan(&state).next_row, None);
+        state.selected_card = (0, 2);
+        assert_eq!(prefetch.plan(&state).next_row.as_deref(), Some("next"));
+        // Only one at a time
+        state.is_loading_row = true;
+        assert_eq!(prefetch.plan(&state).next_row, None);
+    }
+
+    #[test]
+    fn failures_retry_when_they_come_back_into_range() {
+        let mut prefetch = Prefetch::new(0, 1);
+        let mut state = state(&[8]);
+        state.rows[0].cards[0].image = CardImage::Failure("https://example.com/0/0.jpg".into());
+        for (focus, retried) in [(0, true), (1, false), (5, false), (0, true)] {
+            state.selected_card = (focus, 0);
+            let plan = prefetch.plan(&state);
+            assert_eq!(
+                requested(&plan).contains(&(0, 0)),
+                retried,
+                "focus at {}",
+                focus
+            );
+            prefetch.in_range = plan.in_range.unwrap();
+        }
+    }
+
+    #[test]
+    fn collections_load_lines_around_the_focused_one() {
+        let prefetch = Prefetch::new(1, 0);
+        let mut state = state(&[3, 20]);
+        state.push_page(Page::Collection(1));
+        state.selected_card = (9, 1);
+        let plan = prefetch.plan(&state);
+        let lines = COLLECTION_COLUMNS..COLLECTION_COLUMNS * 4;
+        assert_eq!(in_range(&plan), lines.map(|x| (x, 1)).collect::<Vec<_>>());
+    }
+}
@@ -1,8 +1,94 @@
 in vec2 v_uv;
+in vec2 v_pos;
+flat in vec2 v_half_size;
+flat in vec4 v_uv_rect;
+flat in vec4 v_style;
+flat in vec4 v_fill;
 out vec4 frag;
 
-uniform sampler2D tex;
+// Atlas pages bound for this draw, picked per instance
+uniform sampler2D page0;
+uniform sampler2D page1;
+uniform sampler2D page2;
+uniform sampler2D page3;
+
+uniform float time;
+uniform vec3 focus_color;
+
+const float MODE_TEXTURE = 0.;
+
+const float RING_WIDTH = .008;
+const float GLOW_SIZE = .04;
+const vec2 SHADOW_OFFSET = vec2(0., -.015);
+const float SHADOW_BLUR = .05;
+
+// Signed distance to a rounded box centered on the origin
+float rounded_box(vec2 p, vec2 half_size, float r) {
+  vec2 q = abs(p) - half_size + r;
+  return length(max(q, 0.)) + min(max(q.x, q.y), 0.) - r;
+}
+
+// Composite straight-alpha colors
+vec4 over(vec4 top, vec4 bottom) {
+  float a = top.a + bottom.a * (1. - top.a);
+  vec3 c = (top.rgb * top.a + bottom.rgb * bottom.a * (1. - top.a)) / max(a, 1e-4);
+  return vec4(c, a);
+}
+
+// Gradients are taken up front because the page lookup branches per instance
+vec4 sample_page(int page, vec2 uv, vec2 dx, vec2 dy) {
+  if (page == 1) return textureGrad(page1, uv, dx, dy);
+  if (page == 2) return textureGrad(page2, uv, dx, dy);
+  if (page == 3) return textureGrad(page3, uv, dx, dy);
+  return textureGrad(page0, uv, dx, dy);
+}
 
 void main() {
-  frag = texture(tex, v_uv);
+  float radius = v_style.x;
+  float focus = v_style.y;
+  float shadow = v_style.z;
+  float opacity = v_fill.x;
+  float mode = v_fill.y;
+  int page = int(v_fill.z + .5);
+  bool decorations = v_fill.w > .5;
+
+  // Positions are in units of screen height so corners stay round whatever the window shape
+  vec2 p = v_pos;
+  float r = min(radius, v_half_size.y);
+  float d = rounded_box(p, v_half_size, r);
+  float aa = fwidth(d);
+
+  vec2 atlas_uv = mix(v_uv_rect.xy, v_uv_rect.zw, clamp(v_uv, 0., 1.));
+  vec2 dx = dFdx(atlas_uv);
+  vec2 dy = dFdy(atlas_uv);
+
+  vec4 body;
+  if (mode == MODE_TEXTURE) {
+    body = sample_page(page, atlas_uv, dx, dy);
+    // Contained images leave transparent bars where the UVs run off the image
+    if (any(lessThan(v_uv, vec2(0.))) || any(greaterThan(v_uv, vec2(1.)))) {
+      body.a = 0.;
+    }
+  } else {
+    // Diagonal highlight band sweeping across the whole screen so neighbouring tiles shimmer together
+    float band = fract(gl_FragCoord.x / 1600. - gl_FragCoord.y / 4000. - time * .5);
+    float highlight = smoothstep(0., .12, band) * (1. - smoothstep(.12, .24, band));
+    body = sample_page(page, atlas_uv, dx, dy) + vec4(vec3(highlight * .06), 0.);
+  }
+  body.a *= opacity * (1. - smoothstep(-aa, aa, d));
+
+  if (!decorations) {
+    frag = body;
+    return;
+  }
+
+  // The ring hugs the outside of the edge and the glow fades out beyond it
+  float ring = smoothstep(-aa, aa, d) * (1. - smoothstep(RING_WIDTH - aa, RING_WIDTH + aa, d));
+  float glow = 1. - smoothstep(0., GLOW_SIZE, d);
+  vec4 focus_layer = vec4(focus_color, focus * max(ring, glow * .35));
+
+  float shadow_d = rounded_box(p - SHADOW_OFFSET, v_half_size, r);
+  vec4 shadow_layer = vec4(0., 0., 0., shadow * (1. - smoothstep(-SHADOW_BLUR * .5, SHADOW_BLUR, shadow_d)));
+
+  frag = over(body, over(focus_layer, shadow_layer));
 }
@@ -1,8 +1,19 @@
 
-uniform vec2 position;
-uniform float weight;
+uniform float screen_aspect;
+
+in vec2 position;
+in vec2 size;
+in vec4 uv_rect;
+in vec2 uv_scale;
+in vec4 style;
+in vec4 fill;
 
 out vec2 v_uv;
+out vec2 v_pos;
+flat out vec2 v_half_size;
+flat out vec4 v_uv_rect;
+flat out vec4 v_style;
+flat out vec4 v_fill;
 
 const vec2[4] QUAD_POS = vec2[](
   vec2(-1., -1.),
@@ -12,8 +23,20 @@ const vec2[4] QUAD_POS = vec2[](
 );
 
 void main() {
-  vec2 p = QUAD_POS[gl_VertexID];
+  float weight = size.x;
+  float tile_aspect = size.y;
+  float margin = style.w;
+
+  // Lay the tile out in units of screen height so its shape doesn't depend on the window's.
+  // The quad is grown by the margin so shadows and the focus glow have room outside the tile.
+  vec2 half_size = vec2(weight * tile_aspect, weight);
+  vec2 p = QUAD_POS[gl_VertexID] * (half_size + margin);
 
-  gl_Position = vec4(p * weight + position, 0., 1.);
-  v_uv = p * vec2(1,-1) * .5 + .5; // transform the position of the vertex into UV space
+  gl_Position = vec4(p / vec2(screen_aspect, 1.) + position, 0., 1.);
+  v_pos = p;
+  v_uv = (p / half_size * vec2(1,-1) * .5) * uv_scale + .5; // transform the position of the vertex into UV space
+  v_half_size = half_size;
+  v_uv_rect = uv_rect;
+  v_style = style;
+  v_fill = fill;
 }
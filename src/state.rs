@@ -1,17 +1,30 @@
 use tokio::sync::RwLock;
 
-use crate::model::collection::{Item, Set};
-use std::{fmt::Debug, sync::Arc};
+use crate::anim::{Anim, Easing, Motion};
+use crate::model::collection::{Item, Set, TILE_ASPECT};
+use crate::tex::{TextureHandle, TextureId};
+use crate::tile::{DETAILS_TILE_SIZE, SELECTED_TILE_SIZE, TILE_SIZE};
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
 #[derive(Clone, Debug)]
 pub struct State {
     pub rows: Vec<Row>,
     pub queued_rows: Vec<String>,
     pub is_loading_row: bool,
+    /// Focus on the current page
     pub selected_card: (usize, usize),
-    pub show_modal: bool,
-    pub scroll: f32,
-    pub scroll_target: f32,
+    /// Pages opened on top of each other, with the current one last. Home is always at the bottom.
+    pub pages: Vec<PageEntry>,
+    /// Scroll of the current page
+    pub scroll: Anim,
+    /// Bumped whenever cards may need images downloaded that weren't needed before,
+    /// so prefetching knows to look again even if focus hasn't moved
+    pub image_generation: usize,
+    /// Mirror the layout for right-to-left locales
+    pub rtl: bool,
+    /// Where each card on screen was drawn last frame, as left, bottom, right
+    /// and top edges in clip space, for finding what the mouse is over
+    pub card_rects: Vec<((usize, usize), [f32; 4])>,
 }
 
 impl State {
@@ -21,23 +34,161 @@ impl State {
             queued_rows: Vec::new(),
             is_loading_row: false,
             selected_card: (0, 0),
-            show_modal: false,
-            scroll: 0.,
-            scroll_target: 0.,
+            pages: vec![PageEntry::new(Page::Home)],
+            scroll: Anim::new(0., PAGE_SCROLL_MOTION),
+            image_generation: 0,
+            rtl: crate::text::locale_is_rtl(),
+            card_rects: Vec::new(),
+        }
+    }
+
+    /// The page being shown
+    pub fn page(&self) -> Page {
+        self.pages.last().map_or(Page::Home, |entry| entry.page)
+    }
+
+    /// Open a page over the current one, which keeps its focus and scroll for when it's back
+    pub fn push_page(&mut self, page: Page) {
+        if self.page() == page {
+            return;
+        }
+        if let Some(entry) = self.pages.last_mut() {
+            entry.focus = self.selected_card;
+            entry.scroll = self.scroll.target();
+        }
+        self.pages.push(PageEntry::new(page));
+        // Cards are addressed by where they are in the home page's rows wherever they're shown
+        self.selected_card = match page {
+            Page::Collection(y) => (0, y),
+            _ => (0, 0),
+        };
+        self.scroll.snap(0.);
+    }
+
+    /// Go back to the page below, exactly as it was left. Home can't be closed.
+    pub fn pop_page(&mut self) {
+        if self.pages.len() < 2 {
+            return;
+        }
+        self.pages.pop();
+        if let Some(entry) = self.pages.last() {
+            self.selected_card = entry.focus;
+            self.scroll.snap(entry.scroll);
+        }
+    }
+
+    /// Point cards whose textures were evicted back at their source, so they reload when next drawn
+    pub fn release_textures(&mut self, evicted: &HashMap<TextureId, String>) {
+        self.image_generation += 1;
+        for card in self.rows.iter_mut().flat_map(|row| row.cards.iter_mut()) {
+            for image in std::iter::once(&mut card.image).chain(card.logo.as_mut()) {
+                match image {
+                    CardImage::Texture(handle) => {
+                        if let Some(uri) = evicted.get(&handle.id()) {
+                            *image = CardImage::URI(uri.clone());
+                        }
+                    }
+                    CardImage::Preview { preview, full } if evicted.contains_key(&preview.id()) => {
+                        *image = CardImage::Loading(full.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Move every animation `delta_t` seconds on, towards where the page and focus want it
+    pub fn step(&mut self, delta_t: f32) {
+        let page = self.page();
+        let selected_card = self.selected_card;
+        self.scroll.step(delta_t);
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            row.scroll.step(delta_t);
+            // Raise the title over the row's first card while it has focus
+            let leads_row = selected_card.1 == y
+                && (selected_card.0 as f32 - row.scroll.value()).round() as u32 == 0;
+            row.text_height
+                .set_target(if leads_row { 0.3 } else { 0.25 });
+            row.text_height.step(delta_t);
+            for (x, card) in row.cards.iter_mut().enumerate() {
+                let size = match page {
+                    Page::Details(details) if details == (x, y) => DETAILS_TILE_SIZE,
+                    Page::Home | Page::Collection(_) if selected_card == (x, y) => {
+                        SELECTED_TILE_SIZE
+                    }
+                    _ => TILE_SIZE,
+                };
+                card.size.set_target(size);
+                card.size.step(delta_t);
+                // Fade artwork in once it has a texture, and hide it again if it loses it
+                if let CardImage::Texture(_) | CardImage::Preview { .. } = card.image {
+                    card.fade.set_target(1.);
+                    card.fade.step(delta_t);
+                } else {
+                    card.fade.snap(0.);
+                }
+            }
         }
     }
 }
 
 pub type AsyncState = Arc<RwLock<State>>;
 
+/// A screen of the app
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Page {
+    Home,
+    /// Details of the card at `(x, y)` in the home page's rows
+    Details((usize, usize)),
+    /// All of one of the home page's rows, laid out in a grid
+    Collection(usize),
+    Search,
+    Settings,
+}
+
+/// A page on the stack, with the focus and scroll it had when another page was opened over it
 #[derive(Clone, Debug)]
-pub struct Row {
+pub struct PageEntry {
+    pub page: Page,
+    pub focus: (usize, usize),
     pub scroll: f32,
-    pub scroll_target: f32,
-    pub text_height: f32,
-    pub text_height_target: f32,
+}
+
+impl PageEntry {
+    fn new(page: Page) -> Self {
+        Self {
+            page,
+            focus: (0, 0),
+            scroll: 0.,
+        }
+    }
+}
+
+/// Cards on each line of a collection page's grid
+pub const COLLECTION_COLUMNS: usize = 4;
+
+pub const PAGE_SCROLL_MOTION: Motion = Motion::Decay { rate: 12. };
+pub const ROW_SCROLL_MOTION: Motion = Motion::Spring { omega: 14. };
+pub const CARD_SIZE_MOTION: Motion = Motion::Spring { omega: 18. };
+pub const CARD_FADE_MOTION: Motion = Motion::Tween {
+    duration: 0.3,
+    easing: Easing::EASE_IN_OUT,
+};
+pub const TEXT_HEIGHT_MOTION: Motion = Motion::Tween {
+    duration: 0.2,
+    easing: Easing::EASE_OUT,
+};
+
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub scroll: Anim,
+    pub text_height: Anim,
+    /// Width over height of the tiles on this shelf
+    pub aspect: f32,
     pub title: String,
     pub cards: Vec<Card>,
+    /// The card focus returns to when moving back into this row
+    pub focused: Option<usize>,
 }
 
 impl From<&Set> for Option<Row> {
@@ -49,12 +200,12 @@ impl From<&Set> for Option<Row> {
         } = &set
         {
             Some(Row {
-                scroll: 0.,
-                scroll_target: 0.,
-                text_height: 0.,
-                text_height_target: 0.,
+                scroll: Anim::new(0., ROW_SCROLL_MOTION),
+                text_height: Anim::new(0., TEXT_HEIGHT_MOTION),
+                aspect: TILE_ASPECT,
                 title: text.get_name()?,
                 cards: items.iter().filter_map(|item| item.into()).collect(),
+                focused: None,
             })
         } else {
             None
@@ -66,7 +217,12 @@ impl From<&Set> for Option<Row> {
 pub struct Card {
     pub title: String,
     pub image: CardImage,
-    pub size: f32,
+    /// Transparent title treatment art, shown on the details modal
+    pub logo: Option<CardImage>,
+    pub size: Anim,
+    /// Opacity of the artwork over its placeholder
+    pub fade: Anim,
+    pub description: Option<String>,
     pub ratings: Vec<String>,
     pub releases: Vec<String>,
 }
@@ -76,7 +232,16 @@ impl From<&Item> for Option<Card> {
         Some(Card {
             title: item.text.get_name()?,
             image: CardImage::URI(item.image.tile.as_ref()?.image.get_uri()?),
-            size: 0.,
+            logo: item
+                .image
+                .title_treatment
+                .as_ref()
+                .and_then(|t| t.image.get_uri())
+                // Ask for PNG so the alpha channel survives
+                .map(|uri| CardImage::URI(crate::api::with_query(&uri, "format", Some("png")))),
+            size: Anim::new(0., CARD_SIZE_MOTION),
+            fade: Anim::new(0., CARD_FADE_MOTION),
+            description: item.text.get_description(),
             ratings: item
                 .ratings
                 .as_ref()
@@ -96,21 +261,59 @@ impl From<&Item> for Option<Card> {
     }
 }
 
+impl Card {
+    pub fn image_mut(&mut self, slot: ImageSlot) -> Option<&mut CardImage> {
+        match slot {
+            ImageSlot::Tile => Some(&mut self.image),
+            ImageSlot::Logo => self.logo.as_mut(),
+        }
+    }
+}
+
+/// Which of a card's images a download is for
+#[derive(Clone, Copy, Debug)]
+pub enum ImageSlot {
+    Tile,
+    Logo,
+}
+
 #[derive(Clone)]
 pub enum CardImage {
     URI(String),
-    Loading(u32),
-    Texture(u32),
-    Failure,
+    /// Holds the handle the download will be uploaded under
+    Loading(TextureHandle),
+    /// A low resolution version is showing while the full image downloads
+    Preview {
+        preview: TextureHandle,
+        full: TextureHandle,
+    },
+    Texture(TextureHandle),
+    /// Holds the URI so the image can be retried later
+    Failure(String),
+}
+
+impl CardImage {
+    /// The handle of the full image still being downloaded, if there is one
+    pub fn pending(&self) -> Option<&TextureHandle> {
+        match self {
+            Self::Loading(full) | Self::Preview { full, .. } => Some(full),
+            _ => None,
+        }
+    }
 }
 
 impl Debug for CardImage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::URI(arg0) => f.debug_tuple("URI").field(arg0).finish(),
-            Self::Loading(_) => f.write_str("Loading"),
-            Self::Texture(_) => f.write_str("Texture"),
-            Self::Failure => f.write_str("failure"),
+            Self::Loading(handle) => f.debug_tuple("Loading").field(handle).finish(),
+            Self::Preview { preview, full } => f
+                .debug_struct("Preview")
+                .field("preview", preview)
+                .field("full", full)
+                .finish(),
+            Self::Texture(handle) => f.debug_tuple("Texture").field(handle).finish(),
+            Self::Failure(_) => f.write_str("failure"),
         }
     }
 }
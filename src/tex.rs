@@ -1,50 +1,621 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Weak,
+    },
+};
 
-use image::GenericImageView;
-use luminance::texture::{GenMipmaps, Sampler};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, RgbaImage};
+use luminance::texture::{GenMipmaps, MagFilter, MinFilter, Sampler};
 use luminance_front::texture::Texture;
 use luminance_glfw::GL33Context;
 use tokio::sync::RwLock;
 
-use crate::vertex::RGBTexture;
+use crate::{
+    action::{self, Action},
+    state,
+    vertex::RGBATexture,
+};
+
+/// Width and height of an atlas page
+const PAGE_SIZE: u32 = 2048;
+/// Texels of repeated edge kept around each image, so mipmaps don't blend in its neighbours
+const GUTTER: u32 = 8;
+/// Mipmap levels below the base. Each one halves the gutter, so this stops while it's still a texel wide.
+const MIPMAPS: usize = 3;
+/// Shelf heights are rounded up to a multiple of this so similarly sized images share shelves
+const SHELF_STEP: u32 = 16;
+/// GPU memory taken by one page, with the mip chain adding another third on top of the base level
+const PAGE_BYTES: usize = PAGE_SIZE as usize * PAGE_SIZE as usize * 4 * 4 / 3;
+
+/// Names one image in the atlas
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureId(u32);
+
+/// A counted reference to a texture. Cards hold these while they're loading or
+/// showing an image, and the atlas frees the image once the last one is dropped.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TextureHandle(Arc<TextureId>);
+
+impl TextureHandle {
+    pub fn id(&self) -> TextureId {
+        *self.0
+    }
+}
+
+impl Debug for TextureHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TextureHandle").field(&self.0 .0).finish()
+    }
+}
+
+/// Drawn under artwork that's still on its way, with the shimmer on top
+pub const PLACEHOLDER: TextureId = TextureId(0);
+/// Drawn in place of artwork that failed to load
+pub const FALLBACK: TextureId = TextureId(1);
+/// Ids below this are the built in textures above
+const FIRST_IMAGE_ID: u32 = 2;
+/// Color of the placeholder, as 0 to 1 RGB
+const PLACEHOLDER_COLOR: [f32; 3] = [0.12, 0.13, 0.17];
+/// Color of the fallback, which is shaded across its height so it doesn't read as a flat box
+const FALLBACK_COLOR: [f32; 3] = [0.04, 0.15, 0.36];
+
+/// Hands out texture handles. Cheap to clone and safe to use off the render thread.
+#[derive(Clone)]
+pub struct TextureRegistry {
+    next_id: Arc<AtomicU32>,
+    /// Held for as long as the registry lives, so the built in textures are never released
+    placeholder: TextureHandle,
+    fallback: TextureHandle,
+}
+
+impl Default for TextureRegistry {
+    fn default() -> Self {
+        Self {
+            next_id: Arc::new(AtomicU32::new(FIRST_IMAGE_ID)),
+            placeholder: TextureHandle(Arc::new(PLACEHOLDER)),
+            fallback: TextureHandle(Arc::new(FALLBACK)),
+        }
+    }
+}
+
+impl TextureRegistry {
+    pub fn allocate(&self) -> TextureHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        TextureHandle(Arc::new(TextureId(id)))
+    }
+
+    pub fn placeholder(&self) -> TextureHandle {
+        self.placeholder.clone()
+    }
+
+    pub fn fallback(&self) -> TextureHandle {
+        self.fallback.clone()
+    }
+
+    /// The placeholder and fallback, ready to queue for upload at startup
+    pub fn built_in_images(&self) -> Vec<QueuedImage> {
+        let to_rgba = |color: [f32; 3], shade: f32| {
+            let [r, g, b] = color.map(|c| ((c * shade).min(1.) * 255.).round() as u8);
+            image::Rgba([r, g, b, 255])
+        };
+        let placeholder = RgbaImage::from_pixel(4, 4, to_rgba(PLACEHOLDER_COLOR, 1.));
+        let fallback = RgbaImage::from_fn(4, 64, |_, y| {
+            to_rgba(FALLBACK_COLOR, 0.8 + 0.4 * y as f32 / 63.)
+        });
+        vec![
+            QueuedImage {
+                handle: self.placeholder(),
+                uri: String::new(),
+                image: DynamicImage::ImageRgba8(placeholder),
+            },
+            QueuedImage {
+                handle: self.fallback(),
+                uri: String::new(),
+                image: DynamicImage::ImageRgba8(fallback),
+            },
+        ]
+    }
+}
+
+/// A decoded image waiting to be uploaded on the render thread
+pub struct QueuedImage {
+    pub handle: TextureHandle,
+    /// Where the image came from, so it can be fetched again after eviction
+    pub uri: String,
+    pub image: DynamicImage,
+}
+
+pub type QueuedImages = Arc<RwLock<Vec<QueuedImage>>>;
+
+/// Where an image lives in the atlas
+#[derive(Clone, Copy, Debug)]
+pub struct AtlasSlot {
+    pub page: usize,
+    /// Left, top, right and bottom of the image in the page's UV space
+    pub uv_rect: [f32; 4],
+    /// Width over height of the original image
+    pub aspect: f32,
+}
+
+/// One texture of the atlas, with images packed onto shelves at their own aspect ratio
+struct AtlasPage {
+    texture: RGBATexture,
+    shelves: Shelves,
+}
+
+struct ResidentImage {
+    /// Lets go of the image once no card holds a handle to it
+    handle: Weak<TextureId>,
+    slot: AtlasSlot,
+    /// Space taken in the page, including the gutter
+    origin: [u32; 2],
+    size: [u32; 2],
+    uri: String,
+    last_drawn: u64,
+}
+
+/// An image that has space in a page and is ready to upload
+struct PendingUpload {
+    handle: TextureHandle,
+    uri: String,
+    page: usize,
+    origin: [u32; 2],
+    size: [u32; 2],
+    aspect: f32,
+    texels: RgbaImage,
+}
 
 pub struct TextureHost {
-    pub bindable_textures: HashMap<u32, RGBTexture>,
-    pub queued_images: Arc<RwLock<Vec<(u32, image::DynamicImage)>>>,
+    pub queued_images: QueuedImages,
+    pages: Vec<Option<AtlasPage>>,
+    images: HashMap<TextureId, ResidentImage>,
+    /// Most bytes of texture memory to keep around before evicting whole pages
+    budget: usize,
+    frame: u64,
 }
 
 impl TextureHost {
-    pub fn new() -> Self {
-        let bindable_textures: HashMap<u32, RGBTexture> = HashMap::new();
-
-        let queued_images: Arc<RwLock<Vec<(u32, image::DynamicImage)>>> =
-            Arc::new(RwLock::new(Vec::new()));
+    pub fn new(budget: usize) -> Self {
+        let queued_images: QueuedImages = Arc::new(RwLock::new(Vec::new()));
 
         Self {
-            bindable_textures,
             queued_images,
+            pages: Vec::new(),
+            images: HashMap::new(),
+            budget,
+            frame: 0,
+        }
+    }
+
+    /// Look up an image to draw this frame, marking it as recently used
+    pub fn get(&mut self, id: TextureId) -> Option<AtlasSlot> {
+        let frame = self.frame;
+        self.images.get_mut(&id).map(|resident| {
+            resident.last_drawn = frame;
+            resident.slot
+        })
+    }
+
+    /// Borrow the textures of the given pages, in the order they're asked for
+    pub fn pages_mut(&mut self, pages: &[usize]) -> Vec<&mut RGBATexture> {
+        let mut all: Vec<Option<&mut RGBATexture>> = self
+            .pages
+            .iter_mut()
+            .map(|page| page.as_mut().map(|page| &mut page.texture))
+            .collect();
+        pages
+            .iter()
+            .filter_map(|&page| all.get_mut(page).and_then(Option::take))
+            .collect()
+    }
+
+    pub async fn process_queued(&mut self, ctxt: &mut GL33Context, state: state::AsyncState) {
+        self.frame += 1;
+        let queued_images = std::mem::take(&mut *self.queued_images.write().await);
+        let mut uploads: Vec<PendingUpload> = queued_images
+            .into_iter()
+            .filter_map(|queued| self.place(ctxt, queued))
+            .collect();
+
+        // Regenerating mipmaps redoes the whole page, so only do it
+        // with the last upload to each page this frame
+        uploads.sort_by_key(|upload| upload.page);
+        let last_per_page: Vec<bool> = (0..uploads.len())
+            .map(|i| uploads.get(i + 1).map(|next| next.page) != Some(uploads[i].page))
+            .collect();
+        for (upload, last) in uploads.into_iter().zip(last_per_page) {
+            let gen_mipmaps = if last {
+                GenMipmaps::Yes
+            } else {
+                GenMipmaps::No
+            };
+            self.upload(upload, gen_mipmaps);
+        }
+        self.release_unused();
+
+        let evicted = self.evict();
+        if !evicted.is_empty() {
+            action::dispatch(&state, Action::TexturesEvicted(evicted)).await;
+        }
+    }
+
+    /// Make room for an image and lay out its texels with the gutter around them
+    fn place(&mut self, ctxt: &mut GL33Context, queued: QueuedImage) -> Option<PendingUpload> {
+        let img = &queued.image;
+        let (width, height) = img.dimensions();
+        let aspect = width as f32 / height as f32;
+        // Anything too big for a page is shrunk to fit, keeping its aspect ratio
+        let max = PAGE_SIZE - GUTTER * 2;
+        let img = if width > max || height > max {
+            Cow::Owned(img.resize(max, max, FilterType::Triangle))
+        } else {
+            Cow::Borrowed(img)
+        };
+        // Every image ends up as 8-bit RGBA, whatever it was decoded as
+        let texels = with_gutter(&img.to_rgba8());
+        let size = [texels.width(), texels.height()];
+        let (page, origin) = self.allocate(ctxt, size)?;
+        Some(PendingUpload {
+            handle: queued.handle,
+            uri: queued.uri,
+            page,
+            origin,
+            size,
+            aspect,
+            texels,
+        })
+    }
+
+    fn upload(&mut self, upload: PendingUpload, gen_mipmaps: GenMipmaps) {
+        let PendingUpload {
+            handle,
+            uri,
+            page,
+            origin,
+            size,
+            aspect,
+            texels,
+        } = upload;
+        let atlas_page = self.pages[page].as_mut().expect("allocated page");
+        let uploaded = atlas_page
+            .texture
+            .upload_part_raw(gen_mipmaps, origin, size, texels.as_raw())
+            .map_err(|e| println!("error while uploading texture: {}", e));
+        if uploaded.is_err() {
+            self.free(page, origin, size);
+            return;
+        }
+
+        // Inset by half a texel so bilinear filtering stays inside the image
+        let texel = 0.5 / PAGE_SIZE as f32;
+        let uv_rect = [
+            (origin[0] + GUTTER) as f32 / PAGE_SIZE as f32 + texel,
+            (origin[1] + GUTTER) as f32 / PAGE_SIZE as f32 + texel,
+            (origin[0] + size[0] - GUTTER) as f32 / PAGE_SIZE as f32 - texel,
+            (origin[1] + size[1] - GUTTER) as f32 / PAGE_SIZE as f32 - texel,
+        ];
+        self.images.insert(
+            handle.id(),
+            ResidentImage {
+                handle: Arc::downgrade(&handle.0),
+                slot: AtlasSlot {
+                    page,
+                    uv_rect,
+                    aspect,
+                },
+                origin,
+                size,
+                uri,
+                last_drawn: self.frame,
+            },
+        );
+    }
+
+    /// Find room for an image of the given size, opening a new page if none has space
+    fn allocate(&mut self, ctxt: &mut GL33Context, size: [u32; 2]) -> Option<(usize, [u32; 2])> {
+        let existing = self.pages.iter_mut().enumerate().find_map(|(i, page)| {
+            let origin = page.as_mut()?.shelves.allocate(size)?;
+            Some((i, origin))
+        });
+        if existing.is_some() {
+            return existing;
+        }
+
+        let texture: RGBATexture = Texture::new_raw(
+            ctxt,
+            [PAGE_SIZE, PAGE_SIZE],
+            MIPMAPS,
+            tile_sampler(),
+            GenMipmaps::No,
+            &vec![0u8; PAGE_SIZE as usize * PAGE_SIZE as usize * 4],
+        )
+        .map_err(|e| println!("error while creating atlas page: {}", e))
+        .ok()?;
+        let mut page = AtlasPage {
+            texture,
+            shelves: Shelves::default(),
+        };
+        let origin = page.shelves.allocate(size)?;
+
+        let index = match self.pages.iter().position(Option::is_none) {
+            Some(index) => {
+                self.pages[index] = Some(page);
+                index
+            }
+            None => {
+                self.pages.push(Some(page));
+                self.pages.len() - 1
+            }
+        };
+        Some((index, origin))
+    }
+
+    /// Return an image's space to its page, dropping the page once it's empty
+    fn free(&mut self, page: usize, origin: [u32; 2], size: [u32; 2]) {
+        if let Some(atlas_page) = self.pages[page].as_mut() {
+            atlas_page.shelves.free(origin, size);
+            if atlas_page.shelves.is_empty() {
+                self.pages[page] = None;
+            }
+        }
+    }
+
+    /// Bytes of GPU memory taken by the atlas
+    fn memory(&self) -> usize {
+        self.pages.iter().flatten().count() * PAGE_BYTES
+    }
+
+    /// Free every image whose handles have all been dropped
+    fn release_unused(&mut self) {
+        let unused: Vec<TextureId> = self
+            .images
+            .iter()
+            .filter(|(_, resident)| resident.handle.strong_count() == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in unused {
+            if let Some(resident) = self.images.remove(&id) {
+                self.free(resident.slot.page, resident.origin, resident.size);
+            }
+        }
+    }
+
+    /// Drop whole pages, least recently drawn first, until we're back under budget.
+    /// A page with anything drawn last frame is on screen and is never evicted.
+    fn evict(&mut self) -> HashMap<TextureId, String> {
+        let mut evicted = HashMap::new();
+        while self.memory() > self.budget {
+            let mut last_drawn: HashMap<usize, u64> = HashMap::new();
+            for (id, resident) in &self.images {
+                // Pages holding a built in texture count as always on screen
+                let drawn = if id.0 < FIRST_IMAGE_ID {
+                    self.frame
+                } else {
+                    resident.last_drawn
+                };
+                let page = last_drawn.entry(resident.slot.page).or_insert(0);
+                *page = (*page).max(drawn);
+            }
+            let page = last_drawn
+                .into_iter()
+                .filter(|&(_, last_drawn)| last_drawn + 1 < self.frame)
+                .min_by_key(|&(_, last_drawn)| last_drawn)
+                .map(|(page, _)| page);
+            let page = match page {
+                Some(page) => page,
+                None => break,
+            };
+            let ids: Vec<TextureId> = self
+                .images
+                .iter()
+                .filter(|(_, resident)| resident.slot.page == page)
+                .map(|(id, _)| *id)
+                .collect();
+            for id in ids {
+                if let Some(resident) = self.images.remove(&id) {
+                    evicted.insert(id, resident.uri);
+                }
+            }
+            self.pages[page] = None;
+        }
+        evicted
+    }
+}
+
+/// Trilinear filtering so artwork shrunk onto small tiles doesn't alias
+fn tile_sampler() -> Sampler {
+    Sampler {
+        min_filter: MinFilter::LinearMipmapLinear,
+        mag_filter: MagFilter::Linear,
+        ..Sampler::default()
+    }
+}
+
+/// Surround an image with `GUTTER` texels of its own edges
+fn with_gutter(img: &RgbaImage) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    RgbaImage::from_fn(width + GUTTER * 2, height + GUTTER * 2, |x, y| {
+        let x = x.saturating_sub(GUTTER).min(width - 1);
+        let y = y.saturating_sub(GUTTER).min(height - 1);
+        *img.get_pixel(x, y)
+    })
+}
+
+/// Packs rectangles into rows ("shelves") running across a page.
+/// Space freed on a shelf is reused by later rectangles that fit it.
+#[derive(Default)]
+struct Shelves {
+    shelves: Vec<Shelf>,
+    used: usize,
+}
+
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// Free spans as x and width, in order
+    free: Vec<[u32; 2]>,
+}
+
+impl Shelves {
+    /// Find a place for a rectangle of `size`, returning its top left corner
+    fn allocate(&mut self, size: [u32; 2]) -> Option<[u32; 2]> {
+        let [width, height] = size;
+        if width > PAGE_SIZE || height > PAGE_SIZE {
+            return None;
         }
+        let shelf_height = (height.div_ceil(SHELF_STEP) * SHELF_STEP).min(PAGE_SIZE);
+
+        // The shortest shelf that fits, as long as it doesn't waste more than half again
+        let mut candidates: Vec<usize> = (0..self.shelves.len())
+            .filter(|&i| {
+                let shelf = &self.shelves[i];
+                shelf.height >= shelf_height && shelf.height <= shelf_height * 3 / 2
+            })
+            .collect();
+        candidates.sort_by_key(|&i| self.shelves[i].height);
+        for i in candidates {
+            let shelf = &mut self.shelves[i];
+            if let Some(span) = shelf.free.iter_mut().find(|span| span[1] >= width) {
+                let x = span[0];
+                span[0] += width;
+                span[1] -= width;
+                shelf.free.retain(|span| span[1] > 0);
+                self.used += 1;
+                return Some([x, shelf.y]);
+            }
+        }
+
+        // Open a new shelf below the others
+        let y = self
+            .shelves
+            .last()
+            .map_or(0, |shelf| shelf.y + shelf.height);
+        if y + shelf_height > PAGE_SIZE {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height: shelf_height,
+            free: Some([width, PAGE_SIZE - width])
+                .filter(|span| span[1] > 0)
+                .into_iter()
+                .collect(),
+        });
+        self.used += 1;
+        Some([0, y])
+    }
+
+    /// Give back the space of a rectangle placed at `origin`
+    fn free(&mut self, origin: [u32; 2], size: [u32; 2]) {
+        let shelf = match self.shelves.iter_mut().find(|shelf| shelf.y == origin[1]) {
+            Some(shelf) => shelf,
+            None => return,
+        };
+        let index = shelf.free.partition_point(|span| span[0] < origin[0]);
+        shelf.free.insert(index, [origin[0], size[0]]);
+        // Merge with the neighbouring spans
+        if index + 1 < shelf.free.len() && origin[0] + size[0] == shelf.free[index + 1][0] {
+            shelf.free[index][1] += shelf.free.remove(index + 1)[1];
+        }
+        if index > 0 && shelf.free[index - 1][0] + shelf.free[index - 1][1] == origin[0] {
+            let merged = shelf.free.remove(index)[1];
+            shelf.free[index - 1][1] += merged;
+        }
+        self.used -= 1;
+
+        // Empty shelves at the bottom can be reopened at any height
+        while self
+            .shelves
+            .last()
+            .is_some_and(|shelf| shelf.free == [[0, PAGE_SIZE]])
+        {
+            self.shelves.pop();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.used == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: ([u32; 2], [u32; 2]), b: ([u32; 2], [u32; 2])) -> bool {
+        let ((a_origin, a_size), (b_origin, b_size)) = (a, b);
+        (0..2).all(|axis| {
+            a_origin[axis] < b_origin[axis] + b_size[axis]
+                && b_origin[axis] < a_origin[axis] + a_size[axis]
+        })
+    }
+
+    #[test]
+    fn packs_without_overlap() {
+        let mut shelves = Shelves::default();
+        let sizes = [[516, 297], [316, 185], [516, 297], [1000, 40], [116, 73]];
+        let mut placed: Vec<([u32; 2], [u32; 2])> = Vec::new();
+        for &size in sizes.iter().cycle().take(20) {
+            let origin = shelves.allocate(size).expect("room left");
+            assert!(origin[0] + size[0] <= PAGE_SIZE && origin[1] + size[1] <= PAGE_SIZE);
+            for &other in &placed {
+                assert!(!overlaps((origin, size), other));
+            }
+            placed.push((origin, size));
+        }
+    }
+
+    #[test]
+    fn fills_up() {
+        let mut shelves = Shelves::default();
+        let size = [516, 297];
+        // Three across and six down, with shelves rounded up to 304 texels
+        for _ in 0..18 {
+            assert!(shelves.allocate(size).is_some());
+        }
+        assert_eq!(shelves.allocate(size), None);
+    }
+
+    #[test]
+    fn reuses_freed_space() {
+        let mut shelves = Shelves::default();
+        let size = [516, 297];
+        let origins: Vec<[u32; 2]> = (0..18).map(|_| shelves.allocate(size).unwrap()).collect();
+        shelves.free(origins[4], size);
+        assert_eq!(shelves.allocate(size), Some(origins[4]));
+        for &origin in &origins {
+            shelves.free(origin, size);
+        }
+        assert!(shelves.is_empty());
+        assert_eq!(shelves.allocate([2048, 2048]), Some([0, 0]));
+    }
+
+    #[test]
+    fn gutter_repeats_edges() {
+        let img = RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8, 0, 0, 255]));
+        let padded = with_gutter(&img);
+        assert_eq!(padded.dimensions(), (2 + GUTTER * 2, 1 + GUTTER * 2));
+        assert_eq!(padded.get_pixel(0, 0)[0], 0);
+        assert_eq!(padded.get_pixel(GUTTER + 1, GUTTER)[0], 1);
+        assert_eq!(padded.get_pixel(GUTTER * 2 + 1, GUTTER * 2)[0], 1);
     }
 
-    pub async fn process_queued(&mut self, ctxt: &mut GL33Context) {
-        let mut queued_images = self.queued_images.write().await;
-        for loaded_image in queued_images.iter() {
-            let img = &loaded_image.1;
-            let (width, height) = img.dimensions();
-            let texels = img.as_bytes();
-            let new_tex: RGBTexture = Texture::new_raw(
-                ctxt,
-                [width, height],
-                0,
-                Sampler::default(),
-                GenMipmaps::No,
-                texels,
-            )
-            .map_err(|e| println!("error while creating texture: {}", e))
-            .ok()
-            .expect("load displacement map");
-            self.bindable_textures.insert(loaded_image.0, new_tex);
-        }
-        queued_images.clear();
+    #[test]
+    fn built_in_textures_have_their_own_ids() {
+        let registry = TextureRegistry::default();
+        assert_eq!(registry.placeholder().id(), PLACEHOLDER);
+        assert_eq!(registry.fallback().id(), FALLBACK);
+        let ids: Vec<TextureId> = (0..3).map(|_| registry.allocate().id()).collect();
+        assert!(!ids.contains(&PLACEHOLDER) && !ids.contains(&FALLBACK));
+        let built_in: Vec<TextureId> = registry
+            .built_in_images()
+            .iter()
+            .map(|queued| queued.handle.id())
+            .collect();
+        assert_eq!(built_in, [PLACEHOLDER, FALLBACK]);
     }
 }
@@ -10,5 +10,7 @@ void main() {
     if (alpha <= 0.0) {
         discard;
     }
-    out_color = f_color * vec4(1.0, 1.0, 1.0, alpha);
+    // Premultiplied, so glyph edges blend over whatever is underneath
+    float a = f_color.a * alpha;
+    out_color = vec4(f_color.rgb * a, a);
 }
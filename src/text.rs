@@ -1,4 +1,10 @@
-use glyph_brush::{BrushAction, BrushError, GlyphBrush};
+use std::{borrow::Cow, collections::HashMap, io::ErrorKind};
+
+use glyph_brush::{
+    ab_glyph::{Font, FontArc},
+    BrushAction, BrushError, FontId, GlyphBrush, GlyphCruncher, HorizontalAlign, Layout, Section,
+    Text,
+};
 use luminance::{
     blending::{Blending, Equation, Factor},
     pipeline::{PipelineError, TextureBinding},
@@ -20,6 +26,376 @@ use crate::{vertex::*, HEIGHT, WIDTH};
 #[derive(UniformInterface)]
 pub struct TextShaderInterface {
     pub tex: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    /// Shift applied to every glyph, in clip space
+    pub offset: Uniform<[f32; 2]>,
+    /// Draw glyphs in `shadow_color` instead of their own color
+    pub shadow: Uniform<bool>,
+    pub shadow_color: Uniform<[f32; 4]>,
+}
+
+/// Extra pass drawn under the text so it stays readable over bright artwork
+#[derive(Clone, Copy, Debug)]
+pub enum TextEffect {
+    None,
+    /// A copy of the text `offset` window pixels down and to the right
+    DropShadow {
+        offset: f32,
+        color: [f32; 4],
+    },
+    /// Copies of the text `width` window pixels out in every direction
+    Outline {
+        width: f32,
+        color: [f32; 4],
+    },
+}
+
+impl TextEffect {
+    /// The effect named by the environment, a drop shadow if it names none
+    pub fn from_env() -> Self {
+        let color = [0., 0., 0., 0.6];
+        match std::env::var(TEXT_EFFECT_VAR).as_deref() {
+            Ok("none") => TextEffect::None,
+            Ok("outline") => TextEffect::Outline { width: 1.5, color },
+            Ok("shadow") | Err(_) => TextEffect::DropShadow { offset: 1.5, color },
+            Ok(other) => {
+                println!(
+                    "error while reading {}: unknown effect {}",
+                    TEXT_EFFECT_VAR, other
+                );
+                TextEffect::DropShadow { offset: 1.5, color }
+            }
+        }
+    }
+
+    /// Offsets in window pixels to draw the effect at, and its color
+    fn passes(&self) -> (Vec<[f32; 2]>, [f32; 4]) {
+        match *self {
+            TextEffect::None => (Vec::new(), [0.; 4]),
+            TextEffect::DropShadow { offset, color } => (vec![[offset, offset]], color),
+            TextEffect::Outline { width, color } => {
+                let offsets = (0..8)
+                    .map(|i| {
+                        let angle = i as f32 * std::f32::consts::FRAC_PI_4;
+                        [angle.cos() * width, angle.sin() * width]
+                    })
+                    .collect();
+                (offsets, color)
+            }
+        }
+    }
+}
+
+/// Regular weight, for metadata and captions
+pub const FONT_REGULAR: FontId = FontId(0);
+/// Bold weight, for titles
+pub const FONT_BOLD: FontId = FontId(1);
+/// Lists fonts to try, in order, for characters Open Sans doesn't cover
+const FALLBACK_LIST_PATH: &str = "fonts/fallback.txt";
+/// Fallback fonts come after the weights
+const FIRST_FALLBACK: usize = 2;
+/// Picks the text effect: `none`, `shadow` or `outline`
+const TEXT_EFFECT_VAR: &str = "TEXT_EFFECT";
+/// Languages written right to left
+const RTL_LANGUAGES: [&str; 6] = ["ar", "fa", "he", "iw", "ur", "yi"];
+
+pub const TITLE_COLOR: [f32; 4] = [1., 1., 1., 1.];
+pub const METADATA_COLOR: [f32; 4] = [0.78, 0.8, 0.85, 1.];
+
+/// Every font weight, in the order of the `FONT_*` ids
+pub fn load_fonts() -> Vec<FontArc> {
+    let regular = FontArc::try_from_slice(include_bytes!("OpenSans-Regular.ttf")).expect("font");
+    let bold = FontArc::try_from_slice(include_bytes!("OpenSans-Bold.ttf")).expect("font");
+    let mut fonts = vec![regular, bold];
+    fonts.extend(load_fallbacks());
+    fonts
+}
+
+/// Every font in the fallback list that's on this machine. The list has one path per
+/// line and may name fonts for several platforms, so missing files are skipped quietly.
+fn load_fallbacks() -> Vec<FontArc> {
+    let list = match std::fs::read_to_string(FALLBACK_LIST_PATH) {
+        Ok(list) => list,
+        Err(e) => {
+            println!("error while reading {}: {}", FALLBACK_LIST_PATH, e);
+            return Vec::new();
+        }
+    };
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|path| {
+            let bytes = match std::fs::read(path) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == ErrorKind::NotFound => return None,
+                Err(e) => {
+                    println!("error while reading {}: {}", path, e);
+                    return None;
+                }
+            };
+            FontArc::try_from_vec(bytes)
+                .map_err(|e| println!("error while loading {}: {}", path, e))
+                .ok()
+        })
+        .collect()
+}
+
+/// Split text into runs so each character is drawn with the first font that has it:
+/// the requested weight, then each fallback in turn. Whitespace stays with the run it's in.
+pub fn styled<'a>(
+    glyph_brush: &GlyphBrush<TextInstance>,
+    text: &'a str,
+    scale: f32,
+    font_id: FontId,
+    color: [f32; 4],
+) -> Vec<Text<'a>> {
+    let fonts = glyph_brush.fonts();
+    let font_for = |c: char| {
+        std::iter::once(font_id)
+            .chain((FIRST_FALLBACK..fonts.len()).map(FontId))
+            .find(|id| fonts[id.0].glyph_id(c).0 != 0)
+            .unwrap_or(font_id)
+    };
+    let text_run = |run: &'a str, id: FontId| {
+        Text::new(run)
+            .with_scale(scale)
+            .with_font_id(id)
+            .with_color(color)
+    };
+
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut current = None;
+    for (i, c) in text.char_indices().filter(|(_, c)| !c.is_whitespace()) {
+        let id = font_for(c);
+        match current {
+            Some(run_id) if run_id != id => {
+                runs.push(text_run(&text[start..i], run_id));
+                start = i;
+                current = Some(id);
+            }
+            None => current = Some(id),
+            _ => {}
+        }
+    }
+    runs.push(text_run(&text[start..], current.unwrap_or(font_id)));
+    runs
+}
+
+/// Whether the user's locale is written right to left, going by the usual environment variables
+pub fn locale_is_rtl() -> bool {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_default();
+    let language = locale
+        .split(&['_', '-', '.'][..])
+        .next()
+        .unwrap_or_default();
+    RTL_LANGUAGES.contains(&language)
+}
+
+fn is_rtl_char(c: char) -> bool {
+    matches!(c, '\u{0590}'..='\u{08FF}' | '\u{FB1D}'..='\u{FDFF}' | '\u{FE70}'..='\u{FEFF}')
+}
+
+/// Reorder text for display, since glyphs are only ever laid out left to right.
+/// Right-to-left runs are reversed with their brackets mirrored, and so is the order
+/// of the runs when the text starts right to left. Punctuation and spaces go with
+/// the run before them, unless that run is embedded in text going the other way.
+/// This covers Hebrew and unjoined Arabic but doesn't shape or nest embeddings.
+pub fn visual_order(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(is_rtl_char) {
+        return Cow::Borrowed(text);
+    }
+    let base_rtl = text
+        .chars()
+        .find(|c| c.is_alphanumeric())
+        .is_some_and(is_rtl_char);
+    let mut runs: Vec<(bool, String)> = Vec::new();
+    for c in text.chars() {
+        let rtl = if c.is_alphanumeric() {
+            is_rtl_char(c)
+        } else {
+            runs.last().map_or(base_rtl, |(rtl, _)| *rtl)
+        };
+        match runs.last_mut() {
+            Some((run_rtl, run)) if *run_rtl == rtl => run.push(c),
+            _ => runs.push((rtl, c.to_string())),
+        }
+    }
+    // Spaces and punctuation after an embedded run belong to the surrounding text
+    for i in 0..runs.len() {
+        if runs[i].0 != base_rtl {
+            let run = &runs[i].1;
+            let end = run.trim_end_matches(|c: char| !c.is_alphanumeric()).len();
+            let trailing = run[end..].to_owned();
+            runs[i].1.truncate(end);
+            match runs.get_mut(i + 1) {
+                Some((_, next)) => next.insert_str(0, &trailing),
+                None if !trailing.is_empty() => runs.push((base_rtl, trailing)),
+                None => {}
+            }
+        }
+    }
+    let runs = runs.into_iter().map(|(rtl, run)| {
+        if rtl {
+            run.chars().rev().map(mirrored_bracket).collect()
+        } else {
+            run
+        }
+    });
+    Cow::Owned(if base_rtl {
+        runs.rev().collect()
+    } else {
+        runs.collect()
+    })
+}
+
+/// The bracket facing the other way, which is how brackets read in right-to-left text
+fn mirrored_bracket(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        _ => c,
+    }
+}
+
+/// Flip a section to the other side of the screen for right-to-left locales,
+/// anchoring it by its right edge instead of its left
+pub fn mirrored(section: Section, rtl: bool) -> Section {
+    if !rtl {
+        return section;
+    }
+    let (x, y) = section.screen_position;
+    Section {
+        screen_position: (WIDTH as f32 * 2. - x, y),
+        layout: section.layout.h_align(HorizontalAlign::Right),
+        ..section
+    }
+}
+
+/// Scale a color's alpha so a section can fade along with whatever it belongs to
+pub fn faded(color: [f32; 4], opacity: f32) -> [f32; 4] {
+    [
+        color[0],
+        color[1],
+        color[2],
+        color[3] * opacity.clamp(0., 1.),
+    ]
+}
+
+/// Text, scale, font and width in whole layout pixels of a line that was cut short
+type EllipsisKey = (String, u32, FontId, u32);
+
+/// Lines already cut short with an ellipsis, since finding the cut lays the text out
+/// many times. Lines that weren't asked for during a frame are dropped after the next.
+#[derive(Default)]
+pub struct EllipsisCache {
+    current: HashMap<EllipsisKey, String>,
+    previous: HashMap<EllipsisKey, String>,
+}
+
+impl EllipsisCache {
+    /// Start a new frame, forgetting lines that went unused for the whole last one
+    pub fn next_frame(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// The line [`ellipsize`] would give, worked out again only when it wasn't needed last frame
+    pub fn ellipsize(
+        &mut self,
+        glyph_brush: &mut GlyphBrush<TextInstance>,
+        text: &str,
+        scale: f32,
+        font_id: FontId,
+        max_width: f32,
+    ) -> &str {
+        // Widths follow tile animations, so they're rounded to keep from missing every frame
+        let max_width = max_width.floor();
+        let key = (
+            text.to_owned(),
+            scale.to_bits(),
+            font_id,
+            max_width.to_bits(),
+        );
+        let Self { current, previous } = self;
+        current.entry(key).or_insert_with_key(|key| {
+            previous
+                .remove(key)
+                .unwrap_or_else(|| ellipsize(glyph_brush, text, scale, font_id, max_width))
+        })
+    }
+}
+
+/// Cut a single line of text short with an ellipsis so it fits in `max_width` layout pixels.
+/// The cut is made at the logical end, so the result still needs putting in visual order.
+fn ellipsize(
+    glyph_brush: &mut GlyphBrush<TextInstance>,
+    text: &str,
+    scale: f32,
+    font_id: FontId,
+    max_width: f32,
+) -> String {
+    let mut width = |text: &str| {
+        let text = visual_order(text);
+        let runs = styled(glyph_brush, &text, scale, font_id, TITLE_COLOR);
+        glyph_brush
+            .glyph_bounds(
+                Section::default()
+                    .with_text(runs)
+                    .with_layout(Layout::default_single_line()),
+            )
+            .map(|bounds| bounds.width())
+            .unwrap_or(0.)
+    };
+    if width(text) <= max_width {
+        return text.to_owned();
+    }
+
+    // Find the longest prefix that still fits with the ellipsis on the end
+    let ends: Vec<usize> = text.char_indices().map(|(i, _)| i).skip(1).collect();
+    let (mut lo, mut hi) = (0, ends.len());
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if width(&format!("{}…", text[..ends[mid - 1]].trim_end())) <= max_width {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    match lo {
+        0 => "…".to_owned(),
+        n => format!("{}…", text[..ends[n - 1]].trim_end()),
+    }
+}
+
+/// Height in layout pixels of a section once it's laid out, for stacking sections
+pub fn section_height(glyph_brush: &mut GlyphBrush<TextInstance>, section: &Section) -> f32 {
+    glyph_brush
+        .glyph_bounds(section)
+        .map(|bounds| bounds.height())
+        .unwrap_or(0.)
+}
+
+/// A blank glyph cache texture of the given size
+fn glyph_texture(ctxt: &mut GL33Context, (width, height): (u32, u32)) -> Texture<Dim2, NormR8UI> {
+    Texture::new(
+        ctxt,
+        [width, height],
+        0,
+        Sampler::default(),
+        GenMipmaps::No,
+        &vec![0u8; width as usize * height as usize],
+    )
+    .expect("luminance texture creation")
 }
 
 const VS_FONT_STR: &str = include_str!("text.vert.glsl");
@@ -30,46 +406,38 @@ pub struct TextRenderer {
     render_state: RenderState,
     texture: Texture<Dim2, NormR8UI>,
     pub tess: Option<Tess<(), (), TextInstance>>,
+    pub effect: TextEffect,
 }
 
 impl TextRenderer {
-    pub fn new(ctxt: &mut GL33Context, glyph_brush: &mut GlyphBrush<TextInstance>) -> Self {
+    pub fn new(
+        ctxt: &mut GL33Context,
+        glyph_brush: &mut GlyphBrush<TextInstance>,
+        effect: TextEffect,
+    ) -> Self {
         let program = ctxt
             .new_shader_program::<VertexSemantics, (), TextShaderInterface>()
             .from_strings(VS_FONT_STR, None, None, FS_FONT_STR)
             .expect("Program creation")
             .ignore_warnings();
 
+        // The fragment shader outputs premultiplied alpha
         let render_state = RenderState::default()
             .set_blending(Blending {
                 equation: Equation::Additive,
-                src: Factor::SrcAlpha,
-                dst: Factor::Zero,
+                src: Factor::One,
+                dst: Factor::SrcAlphaComplement,
             })
             .set_depth_test(None);
 
-        let texture = Texture::new(
-            ctxt,
-            [
-                glyph_brush.texture_dimensions().0,
-                glyph_brush.texture_dimensions().1,
-            ],
-            0,
-            Sampler::default(),
-            GenMipmaps::No,
-            &vec![
-                0u8;
-                glyph_brush.texture_dimensions().0 as usize
-                    * glyph_brush.texture_dimensions().1 as usize
-            ],
-        )
-        .expect("luminance texture creation");
+        let texture = glyph_texture(ctxt, glyph_brush.texture_dimensions());
 
         Self {
             program,
             render_state,
             texture,
             tess: None,
+            effect,
         }
     }
 
@@ -94,7 +462,9 @@ impl TextRenderer {
 
         if let Err(e) = action {
             let BrushError::TextureTooSmall { suggested } = e;
+            // Every glyph is uploaded again next frame, into a texture big enough for them
             glyph_brush.resize_texture(suggested.0, suggested.1);
+            self.texture = glyph_texture(ctxt, suggested);
             return;
         }
         let action = action.unwrap();
@@ -122,12 +492,148 @@ impl TextRenderer {
             let program = &mut self.program;
             let texture = &mut self.texture;
             let render_state = &self.render_state;
+            let (offsets, shadow_color) = self.effect.passes();
             shd_gate.shade(program, |mut iface, uni, mut rdr_gate| {
                 let bound_tex = pipeline.bind_texture(texture)?;
                 iface.set(&uni.tex, bound_tex.binding());
+
+                // Effect passes go underneath the text itself
+                iface.set(&uni.shadow, true);
+                iface.set(&uni.shadow_color, shadow_color);
+                for offset in offsets {
+                    let offset = [
+                        offset[0] * 2. / WIDTH as f32,
+                        -offset[1] * 2. / HEIGHT as f32,
+                    ];
+                    iface.set(&uni.offset, offset);
+                    rdr_gate.render(render_state, |mut tess_gate| tess_gate.render(tess))?;
+                }
+
+                iface.set(&uni.shadow, false);
+                iface.set(&uni.offset, [0., 0.]);
                 rdr_gate.render(render_state, |mut tess_gate| tess_gate.render(tess))
             })?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glyph_brush::GlyphBrushBuilder;
+
+    use super::*;
+
+    /// The two weights, without any fallbacks from the machine running the tests
+    fn brush() -> GlyphBrush<TextInstance> {
+        GlyphBrushBuilder::using_fonts(load_fonts().into_iter().take(FIRST_FALLBACK).collect())
+            .build()
+    }
+
+    fn width(glyph_brush: &mut GlyphBrush<TextInstance>, text: &str) -> f32 {
+        let runs = styled(glyph_brush, text, 30., FONT_REGULAR, TITLE_COLOR);
+        glyph_brush
+            .glyph_bounds(
+                Section::default()
+                    .with_text(runs)
+                    .with_layout(Layout::default_single_line()),
+            )
+            .map_or(0., |bounds| bounds.width())
+    }
+
+    #[test]
+    fn text_that_fits_is_unchanged() {
+        let mut glyph_brush = brush();
+        let text = "Hello world";
+        let max_width = width(&mut glyph_brush, text);
+        assert_eq!(
+            ellipsize(&mut glyph_brush, text, 30., FONT_REGULAR, max_width),
+            text
+        );
+    }
+
+    #[test]
+    fn cuts_at_the_longest_prefix_that_fits() {
+        let mut glyph_brush = brush();
+        // Room for "Hello…" but not "Hello w…", so the space before the cut goes too
+        let max_width = width(&mut glyph_brush, "Hello…") + 0.5;
+        assert!(width(&mut glyph_brush, "Hello w…") > max_width);
+        let cut = ellipsize(
+            &mut glyph_brush,
+            "Hello world",
+            30.,
+            FONT_REGULAR,
+            max_width,
+        );
+        assert_eq!(cut, "Hello…");
+    }
+
+    #[test]
+    fn too_narrow_for_any_text() {
+        let mut glyph_brush = brush();
+        assert_eq!(
+            ellipsize(&mut glyph_brush, "Hello", 30., FONT_REGULAR, 1.),
+            "…"
+        );
+    }
+
+    #[test]
+    fn cache_forgets_lines_unused_for_a_frame() {
+        let mut glyph_brush = brush();
+        let mut cache = EllipsisCache::default();
+        let line = cache.ellipsize(&mut glyph_brush, "Hello world", 30., FONT_REGULAR, 1.);
+        assert_eq!(line, "…");
+
+        // Still there after a frame it was used in, and carried over when it's used again
+        cache.next_frame();
+        cache.ellipsize(&mut glyph_brush, "Hello world", 30., FONT_REGULAR, 1.);
+        assert_eq!((cache.current.len(), cache.previous.len()), (1, 0));
+
+        cache.next_frame();
+        cache.next_frame();
+        assert!(cache.current.is_empty() && cache.previous.is_empty());
+    }
+
+    #[test]
+    fn left_to_right_text_is_untouched() {
+        assert!(matches!(visual_order("Hello (world)!"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn embedded_right_to_left_keeps_trailing_punctuation_outside() {
+        assert_eq!(visual_order("Hello עולם!"), "Hello םלוע!");
+        assert_eq!(visual_order("Hello עולם, world"), "Hello םלוע, world");
+    }
+
+    #[test]
+    fn embedded_left_to_right_keeps_trailing_punctuation_outside() {
+        assert_eq!(visual_order("שלום world!"), "!world םולש");
+    }
+
+    #[test]
+    fn brackets_are_mirrored_in_right_to_left_text() {
+        assert_eq!(visual_order("(שלום)"), "(םולש)");
+        assert_eq!(visual_order("שלום [עולם]"), "[םלוע] םולש");
+    }
+
+    #[test]
+    fn styled_falls_back_per_run() {
+        // The bold weight has no Hebrew, so it comes from the regular face standing in as a fallback
+        let mut fonts = load_fonts()
+            .into_iter()
+            .take(FIRST_FALLBACK)
+            .collect::<Vec<_>>();
+        fonts.push(fonts[0].clone());
+        let glyph_brush: GlyphBrush<TextInstance> = GlyphBrushBuilder::using_fonts(fonts).build();
+        let runs = styled(&glyph_brush, "Hi שלום world!", 30., FONT_BOLD, TITLE_COLOR);
+        let runs: Vec<(&str, FontId)> = runs.iter().map(|run| (run.text, run.font_id)).collect();
+        assert_eq!(
+            runs,
+            [
+                ("Hi ", FONT_BOLD),
+                ("שלום ", FontId(FIRST_FALLBACK)),
+                ("world!", FONT_BOLD)
+            ]
+        );
+    }
+}
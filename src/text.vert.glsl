@@ -10,6 +10,11 @@ in vec3 left_top;
 in vec2 right_bottom;
 in vec2 tex_left_top;
 in vec2 tex_right_bottom;
+in vec4 color;
+
+uniform vec2 offset;
+uniform bool shadow;
+uniform vec4 shadow_color;
 
 out vec2 f_tex_pos;
 out vec4 f_color;
@@ -41,6 +46,7 @@ void main() {
         break;
     }
 
-    f_color = vec4(1.);
-    gl_Position = vec4(pos, left_top.z, 1.0);
+    // Shadows fade along with the text they belong to
+    f_color = shadow ? vec4(shadow_color.rgb, shadow_color.a * color.a) : color;
+    gl_Position = vec4(pos + offset, left_top.z, 1.0);
 }
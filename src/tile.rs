@@ -1,13 +1,6 @@
-use std::{
-    collections::HashMap,
-    sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc,
-    },
-};
-
-use glyph_brush::{GlyphBrush, Section, Text};
+use glyph_brush::{GlyphBrush, Layout, Section};
 use luminance::{
+    blending::{Blending, Equation, Factor},
     context::GraphicsContext,
     pipeline::{PipelineError, TextureBinding},
     pixel::NormUnsigned,
@@ -17,52 +10,194 @@ use luminance::{
     texture::Dim2,
 };
 use luminance_derive::UniformInterface;
-use luminance_front::{pipeline::Pipeline, shader::Program, shading_gate::ShadingGate, tess::Tess};
+use luminance_front::{
+    pipeline::Pipeline,
+    shader::Program,
+    shading_gate::ShadingGate,
+    tess::{Tess, TessView},
+};
 use luminance_glfw::GL33Context;
-use tokio::sync::RwLock;
 
-use crate::{api, state, vertex::*, HEIGHT, WIDTH};
+use crate::{
+    action::{self, Action},
+    model::collection::TILE_ASPECT,
+    state,
+    tex::{self, TextureHost, TextureId},
+    text::{
+        faded, mirrored, section_height, styled, visual_order, EllipsisCache, FONT_BOLD,
+        FONT_REGULAR, METADATA_COLOR, TITLE_COLOR,
+    },
+    vertex::*,
+    HEIGHT, WIDTH,
+};
 
 const VS_STR: &str = include_str!("shader.vert.glsl");
 const FS_STR: &str = include_str!("shader.frag.glsl");
 
 #[derive(UniformInterface)]
 struct ShaderInterface {
-    position: Uniform<[f32; 2]>,
-    weight: Uniform<f32>,
-    tex: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    page0: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    page1: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    page2: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    page3: Uniform<TextureBinding<Dim2, NormUnsigned>>,
+    time: Uniform<f32>,
+    screen_aspect: Uniform<f32>,
+    focus_color: Uniform<[f32; 3]>,
+}
+
+/// Atlas pages one draw call can sample from, matching the `page` samplers in `shader.frag.glsl`
+const MAX_BOUND_PAGES: usize = 4;
+// Fill modes understood by `shader.frag.glsl`
+const MODE_TEXTURE: f32 = 0.;
+const MODE_PLACEHOLDER: f32 = 1.;
+/// Color of the ring and glow around the focused tile
+const FOCUS_COLOR: [f32; 3] = [0.95, 0.95, 0.95];
+/// Corner radius of tiles, in fractions of the screen height
+const TILE_RADIUS: f32 = 0.025;
+/// Opacity of the drop shadow under tiles
+const TILE_SHADOW: f32 = 0.6;
+/// How far the quad is grown to make room for the shadow and focus glow
+const TILE_MARGIN: f32 = 0.07;
+/// Rough aspect ratio of title treatments, used to pick a download size before the image is known
+pub const LOGO_ASPECT: f32 = 3.;
+/// Size of the title treatment on the details modal
+pub const LOGO_SIZE: f32 = 0.3;
+/// Size of cards that don't have focus
+pub const TILE_SIZE: f32 = 0.32;
+/// Size of the selected card, the largest a tile gets in the grid
+pub const SELECTED_TILE_SIZE: f32 = 0.42;
+/// Size of the card on the details modal
+pub const DETAILS_TILE_SIZE: f32 = 0.75;
+/// Number of placeholder cards drawn for a row that hasn't loaded yet
+const SKELETON_ROW_LEN: usize = 6;
+/// Space kept between text and the edge of the screen, in layout pixels
+const TEXT_MARGIN: f32 = 120.;
+
+/// How artwork is fitted into a tile whose aspect ratio differs from the image's
+#[derive(Clone, Copy)]
+pub enum Fit {
+    /// Fill the tile, cropping the overflowing edges of the image
+    Cover,
+    /// Show the whole image, leaving transparent bars at the sides
+    Contain,
+}
+
+impl Fit {
+    /// Scale to apply to the tile's UVs around their center
+    fn uv_scale(&self, tile_aspect: f32, image_aspect: f32) -> [f32; 2] {
+        let ratio = tile_aspect / image_aspect;
+        match (self, ratio < 1.) {
+            (Fit::Cover, true) | (Fit::Contain, false) => [ratio, 1.],
+            (Fit::Cover, false) | (Fit::Contain, true) => [1., 1. / ratio],
+        }
+    }
+}
+
+pub enum TileFill {
+    Texture(TextureId),
+    /// Transparent art drawn without a placeholder underneath
+    Logo(TextureId),
+    /// Shimmering stand-in while the artwork is on its way
+    Placeholder,
+    /// Drawn in place of artwork that failed to load
+    Failure,
+}
+
+impl TileFill {
+    /// The fill for a card's artwork in whatever state it's in
+    fn of(image: &state::CardImage) -> Self {
+        match image {
+            state::CardImage::Texture(handle)
+            | state::CardImage::Preview {
+                preview: handle, ..
+            } => TileFill::Texture(handle.id()),
+            state::CardImage::Failure(_) => TileFill::Failure,
+            state::CardImage::URI(_) | state::CardImage::Loading(_) => TileFill::Placeholder,
+        }
+    }
 }
 
 pub struct Tile {
     x: f32,
     y: f32,
     size: f32,
-    tex_id: u32,
+    fill: TileFill,
+    /// Opacity of the artwork, the placeholder shows through until it reaches 1
+    opacity: f32,
+    /// Width over height, or `None` to follow the image
+    pub aspect: Option<f32>,
+    pub fit: Fit,
+    /// Corner radius, in fractions of the screen height
+    pub radius: f32,
+    /// Strength of the focus ring and glow, from 0 to 1
+    pub focus: f32,
+    /// Opacity of the drop shadow
+    pub shadow: f32,
+}
+
+impl Tile {
+    /// Half the width in clip space, on a screen with the given width over height
+    fn half_width(&self, screen_aspect: f32) -> f32 {
+        self.size * 0.5 * self.aspect.unwrap_or(TILE_ASPECT) / screen_aspect
+    }
+
+    /// Left, bottom, right and top edges in clip space
+    fn rect(&self, mirrored: bool, screen_aspect: f32) -> [f32; 4] {
+        let half_height = self.size * 0.5;
+        let half_width = self.half_width(screen_aspect);
+        let x = if mirrored { -self.x } else { self.x };
+        [
+            x - half_width,
+            self.y - half_height,
+            x + half_width,
+            self.y + half_height,
+        ]
+    }
+}
+
+/// Tiles drawn with a single instanced call, sampling from up to `MAX_BOUND_PAGES` atlas pages.
+/// The instance buffer is kept from frame to frame and only rebuilt when it's outgrown.
+struct TileBatch {
+    tess: Tess<(), (), TileInstance>,
+    pages: Vec<usize>,
+    /// Instances in use, from the start of the buffer
+    len: usize,
 }
 
 pub struct TileRenderer {
-    pub tess: Tess<()>,
-    program: Program<(), (), ShaderInterface>,
+    program: Program<VertexSemantics, (), ShaderInterface>,
+    render_state: RenderState,
     tiles: Vec<Tile>,
+    batches: Vec<TileBatch>,
+    time: f32,
+    /// Lay tiles out right to left
+    mirrored: bool,
+    /// Width over height of the framebuffer
+    screen_aspect: f32,
+    ellipsized: EllipsisCache,
 }
 
 impl TileRenderer {
     pub fn new(ctxt: &mut GL33Context) -> Self {
-        let tess: Tess<()> = ctxt
-            .new_tess()
-            .set_render_vertex_nb(4)
-            .set_mode(Mode::TriangleFan)
-            .build()
-            .unwrap();
         let program = ctxt
-            .new_shader_program::<(), (), ShaderInterface>()
+            .new_shader_program::<VertexSemantics, (), ShaderInterface>()
             .from_strings(VS_STR, None, None, FS_STR)
             .unwrap()
             .ignore_warnings();
+        let render_state = RenderState::default().set_blending(Blending {
+            equation: Equation::Additive,
+            src: Factor::SrcAlpha,
+            dst: Factor::SrcAlphaComplement,
+        });
         Self {
-            tess,
             program,
+            render_state,
             tiles: Vec::new(),
+            batches: Vec::new(),
+            time: 0.,
+            mirrored: false,
+            screen_aspect: framebuffer_aspect(ctxt),
+            ellipsized: EllipsisCache::default(),
         }
     }
 
@@ -71,175 +206,512 @@ impl TileRenderer {
         delta_t: f32,
         state: state::AsyncState,
         glyph_brush: &mut GlyphBrush<TextInstance>,
-        tex_uid: &AtomicU32,
-        queued_images: Arc<RwLock<Vec<(u32, image::DynamicImage)>>>,
     ) {
         self.tiles.clear();
-        let state_ = Arc::clone(&state);
-        let mut state = state.write().await;
-        let selected_card = state.selected_card;
-        let scroll = state.scroll;
-        let mut scroll_target = state.scroll_target;
-
-        // Dynamically load next row
-        // TODO: Put this in the user input section instead of the tile renderer
-        if selected_card.1 >= state.rows.len().saturating_sub(2)
-            && !state.is_loading_row
-            && state.queued_rows.len() > 0
-        {
-            state.is_loading_row = true;
-            let state_ = state_.clone();
-            tokio::spawn(async move {
-                api::load_next_row(state_.clone()).await;
-                let mut state = state_.write().await;
-                state.is_loading_row = false;
-            });
+        self.ellipsized.next_frame();
+        self.time += delta_t;
+        let card_rects = {
+            let state = state.read().await;
+            self.layout(&state, glyph_brush)
+        };
+        action::dispatch(&state, Action::CardsDrawn(card_rects)).await;
+    }
+
+    /// Queue the current page's tiles and text, returning where each card was drawn
+    fn layout(
+        &mut self,
+        state: &state::State,
+        glyph_brush: &mut GlyphBrush<TextInstance>,
+    ) -> Vec<((usize, usize), [f32; 4])> {
+        let rtl = state.rtl;
+        self.mirrored = rtl;
+        let scroll = state.scroll.value();
+
+        if let state::Page::Collection(y) = state.page() {
+            return self.layout_collection(state, y, glyph_brush);
+        }
+
+        // Pages with nothing to draw yet just show their name
+        let heading = match state.page() {
+            state::Page::Search => Some("Search"),
+            state::Page::Settings => Some("Settings"),
+            state::Page::Home | state::Page::Details(_) | state::Page::Collection(_) => None,
+        };
+        if let Some(heading) = heading {
+            let heading = visual_order(heading);
+            let section = Section::default()
+                .with_text(styled(glyph_brush, &heading, 50., FONT_BOLD, TITLE_COLOR))
+                .with_screen_position((135., 100.))
+                .with_bounds((WIDTH as f32 * 2. - 135. - TEXT_MARGIN, f32::INFINITY))
+                .with_layout(Layout::default_single_line());
+            glyph_brush.queue(mirrored(section, rtl));
+            return Vec::new();
         }
 
         // Modal
         // Show the panel on the left side of the screen and some info on the right
         // TODO: Make a modal function
-        if state.show_modal {
-            let card = state
-                .rows
-                .get_mut(selected_card.1)
-                .and_then(|row| row.cards.get_mut(selected_card.0));
+        if let state::Page::Details((x, y)) = state.page() {
+            let card = state.rows.get(y).and_then(|row| row.cards.get(x));
             if let Some(card) = card {
-                if let state::CardImage::Texture(tex_id) = card.image {
-                    card.size += (0.75 - card.size) * (1. - (1. - delta_t) * 0.7);
+                let fill = TileFill::of(&card.image);
+                let tile = Tile {
+                    x: -0.5,
+                    y: 0.,
+                    size: card.size.value(),
+                    fill,
+                    opacity: card.fade.value(),
+                    aspect: None,
+                    fit: Fit::Contain,
+                    radius: TILE_RADIUS * 2.,
+                    focus: 0.,
+                    shadow: TILE_SHADOW,
+                };
+                if tile.opacity < 1. {
+                    queue_caption(
+                        glyph_brush,
+                        &mut self.ellipsized,
+                        &card.title,
+                        &tile,
+                        40.,
+                        rtl,
+                        self.screen_aspect,
+                    );
+                }
+                self.tiles.push(tile);
+
+                // Title treatment above the metadata
+                if let Some(state::CardImage::Texture(handle)) = &card.logo {
                     self.tiles.push(Tile {
-                        x: -0.5,
-                        y: 0.,
-                        size: card.size,
-                        tex_id: tex_id,
+                        x: 0.45,
+                        y: 0.45,
+                        size: LOGO_SIZE,
+                        fill: TileFill::Logo(handle.id()),
+                        opacity: 1.,
+                        aspect: None,
+                        fit: Fit::Contain,
+                        radius: 0.,
+                        focus: 0.,
+                        shadow: 0.,
                     });
-                    glyph_brush.queue(
-                        Section::default()
-                            .add_text(Text::new(&card.title).with_scale(50.))
-                            .with_screen_position((WIDTH as f32, HEIGHT as f32 - 50.)),
+                }
+
+                // Fade the details in as the card grows into the modal
+                let opacity = (card.size.value() - SELECTED_TILE_SIZE)
+                    / (DETAILS_TILE_SIZE - SELECTED_TILE_SIZE);
+                // Details fill the right half of the screen, stacked top to bottom
+                let left = WIDTH as f32;
+                let width = WIDTH as f32 - TEXT_MARGIN;
+                let mut top = HEIGHT as f32 - 50.;
+                let title = visual_order(&card.title);
+                let title_runs = styled(
+                    glyph_brush,
+                    &title,
+                    50.,
+                    FONT_BOLD,
+                    faded(TITLE_COLOR, opacity),
+                );
+                let title = Section::default()
+                    .with_text(title_runs)
+                    .with_screen_position((left, top))
+                    .with_bounds((width, f32::INFINITY))
+                    .with_layout(Layout::default_wrap());
+                top += section_height(glyph_brush, &title) + 15.;
+                glyph_brush.queue(mirrored(title, rtl));
+                for r in card.ratings.iter().zip(card.releases.iter()) {
+                    let metadata = format!("{}  |  {}", r.0, r.1);
+                    let metadata = visual_order(&metadata);
+                    let runs = styled(
+                        glyph_brush,
+                        &metadata,
+                        35.,
+                        FONT_REGULAR,
+                        faded(METADATA_COLOR, opacity),
                     );
-                    for (i, r) in card.ratings.iter().zip(card.releases.iter()).enumerate() {
-                        glyph_brush.queue(
-                            Section::default()
-                                .add_text(
-                                    Text::new(&format!("{}  |  {}", r.0, r.1)).with_scale(35.),
-                                )
-                                .with_screen_position((
-                                    WIDTH as f32,
-                                    HEIGHT as f32 + 50. * i as f32,
-                                )),
-                        );
-                    }
-                    return;
+                    let section = Section::default()
+                        .with_text(runs)
+                        .with_screen_position((left, top))
+                        .with_bounds((width, 50.))
+                        .with_layout(Layout::default_single_line());
+                    glyph_brush.queue(mirrored(section, rtl));
+                    top += 50.;
+                }
+                if let Some(description) = &card.description {
+                    top += 20.;
+                    let description = visual_order(description);
+                    let runs = styled(
+                        glyph_brush,
+                        &description,
+                        28.,
+                        FONT_REGULAR,
+                        faded(METADATA_COLOR, opacity),
+                    );
+                    let section = Section::default()
+                        .with_text(runs)
+                        .with_screen_position((left, top))
+                        .with_bounds((width, (HEIGHT as f32 * 2. - TEXT_MARGIN - top).max(0.)))
+                        .with_layout(Layout::default_wrap());
+                    glyph_brush.queue(mirrored(section, rtl));
                 }
+                return Vec::new();
             }
         }
 
         let h_spacing = 0.4;
         let v_spacing = 0.6;
-        let safe_area = 0.71;
-
-        for (y, row) in state.rows.iter_mut().enumerate() {
-            row.scroll = lerp(row.scroll, row.scroll_target, 0.9, delta_t);
-            row.text_height = lerp(row.text_height, row.text_height_target, 0.7, delta_t);
-            let y_pos_pre = v_spacing - y as f32 * v_spacing;
-            let y_pos = y_pos_pre - scroll * v_spacing;
-            if selected_card.1 == y && (selected_card.0 as f32 - row.scroll).round() as u32 == 0 {
-                row.text_height_target = 0.3;
-            } else {
-                row.text_height_target = 0.25;
-            }
-            glyph_brush.queue(
-                Section::default()
-                    .add_text(Text::new(&row.title).with_scale(36.))
-                    .with_screen_position((
-                        135.,
-                        HEIGHT as f32 - (y_pos + row.text_height) * HEIGHT as f32,
-                    )),
-            );
-            for (x, card) in row.cards.iter_mut().enumerate() {
-                let is_selected = selected_card == (x, y);
-                let target_size = if is_selected { 0.42 } else { 0.32 };
-                let x_pos_pre = x as f32 * h_spacing - 1. + 0.3;
-                let x_pos = x_pos_pre - row.scroll * h_spacing;
+
+        let mut card_rects = Vec::new();
+        for (y, row) in state.rows.iter().enumerate() {
+            let row_scroll = row.scroll.value();
+            let y_pos = v_spacing - y as f32 * v_spacing - scroll * v_spacing;
+            let title_width = WIDTH as f32 * 2. - 135. - TEXT_MARGIN;
+            let title =
+                self.ellipsized
+                    .ellipsize(glyph_brush, &row.title, 36., FONT_BOLD, title_width);
+            let title = visual_order(title);
+            let section = Section::default()
+                .with_text(styled(glyph_brush, &title, 36., FONT_BOLD, TITLE_COLOR))
+                .with_bounds((title_width, f32::INFINITY))
+                .with_layout(Layout::default_single_line())
+                .with_screen_position((
+                    135.,
+                    HEIGHT as f32 - (y_pos + row.text_height.value()) * HEIGHT as f32,
+                ));
+            glyph_brush.queue(mirrored(section, rtl));
+            for (x, card) in row.cards.iter().enumerate() {
+                let x_pos = x as f32 * h_spacing - 1. + 0.3 - row_scroll * h_spacing;
                 if x_pos > -1.5 && x_pos < 1.5 && y_pos > -1.5 && y_pos < 1.5 {
-                    let img_id: u32 = match &card.image {
-                        state::CardImage::URI(uri) => {
-                            let uri = uri.clone();
-                            card.image = state::CardImage::Loading(1);
-                            let uid = tex_uid.fetch_add(1, Ordering::SeqCst);
-                            tokio::spawn(api::load_card_image(
-                                uri,
-                                state_.clone(),
-                                queued_images.clone(),
-                                x,
-                                y,
-                                uid,
-                            ));
-                            0
-                        }
-                        state::CardImage::Texture(x) => *x,
-                        _ => 0,
-                    };
-                    self.tiles.push(Tile {
-                        tex_id: img_id,
+                    let fill = TileFill::of(&card.image);
+                    let tile = Tile {
                         x: x_pos,
                         y: y_pos,
-                        size: card.size,
-                    });
+                        size: card.size.value(),
+                        fill,
+                        opacity: card.fade.value(),
+                        aspect: Some(row.aspect),
+                        fit: Fit::Cover,
+                        radius: TILE_RADIUS,
+                        focus: focus_ring(card),
+                        shadow: TILE_SHADOW,
+                    };
+                    if tile.opacity < 1. {
+                        queue_caption(
+                            glyph_brush,
+                            &mut self.ellipsized,
+                            &card.title,
+                            &tile,
+                            18.,
+                            rtl,
+                            self.screen_aspect,
+                        );
+                    }
+                    card_rects.push(((x, y), tile.rect(rtl, self.screen_aspect)));
+                    self.tiles.push(tile);
                 }
-                card.size = lerp(card.size, target_size, 0.7, delta_t);
-
-                // If the selected card is out of the safe area, scroll the screen or row to bring it back in
-                if is_selected {
-                    if x_pos_pre - row.scroll_target * h_spacing > safe_area {
-                        row.scroll_target += 1.;
-                    } else if x_pos_pre - row.scroll_target * h_spacing < -safe_area {
-                        row.scroll_target -= 1.;
+            }
+        }
+
+        // Skeleton rows for sets that are queued or still loading
+        let skeleton_rows = state.queued_rows.len() + state.is_loading_row as usize;
+        for y in state.rows.len()..state.rows.len() + skeleton_rows {
+            let y_pos = v_spacing - y as f32 * v_spacing - scroll * v_spacing;
+            if !(-1.5..1.5).contains(&y_pos) {
+                continue;
+            }
+            for x in 0..SKELETON_ROW_LEN {
+                self.tiles.push(Tile {
+                    x: x as f32 * h_spacing - 1. + 0.3,
+                    y: y_pos,
+                    size: TILE_SIZE,
+                    fill: TileFill::Placeholder,
+                    opacity: 0.,
+                    aspect: Some(TILE_ASPECT),
+                    fit: Fit::Cover,
+                    radius: TILE_RADIUS,
+                    focus: 0.,
+                    shadow: TILE_SHADOW,
+                });
+            }
+        }
+        card_rects
+    }
+
+    /// Lay all of row `y`'s cards out in a grid under its title
+    fn layout_collection(
+        &mut self,
+        state: &state::State,
+        y: usize,
+        glyph_brush: &mut GlyphBrush<TextInstance>,
+    ) -> Vec<((usize, usize), [f32; 4])> {
+        let rtl = state.rtl;
+        let scroll = state.scroll.value();
+        let h_spacing = 0.4;
+        let v_spacing = 0.45;
+
+        let mut card_rects = Vec::new();
+        if let Some(row) = state.rows.get(y) {
+            // The title scrolls away with the grid
+            let title_width = WIDTH as f32 * 2. - 135. - TEXT_MARGIN;
+            let title =
+                self.ellipsized
+                    .ellipsize(glyph_brush, &row.title, 50., FONT_BOLD, title_width);
+            let title = visual_order(title);
+            let section = Section::default()
+                .with_text(styled(glyph_brush, &title, 50., FONT_BOLD, TITLE_COLOR))
+                .with_screen_position((135., 100. + scroll * v_spacing * HEIGHT as f32))
+                .with_bounds((title_width, f32::INFINITY))
+                .with_layout(Layout::default_single_line());
+            glyph_brush.queue(mirrored(section, rtl));
+
+            for (x, card) in row.cards.iter().enumerate() {
+                let column = x % state::COLLECTION_COLUMNS;
+                let line = x / state::COLLECTION_COLUMNS;
+                let x_pos = column as f32 * h_spacing - 1. + 0.3;
+                let y_pos = 0.4 - line as f32 * v_spacing - scroll * v_spacing;
+                if y_pos > -1.5 && y_pos < 1.5 {
+                    let fill = TileFill::of(&card.image);
+                    let tile = Tile {
+                        x: x_pos,
+                        y: y_pos,
+                        size: card.size.value(),
+                        fill,
+                        opacity: card.fade.value(),
+                        aspect: Some(row.aspect),
+                        fit: Fit::Cover,
+                        radius: TILE_RADIUS,
+                        focus: focus_ring(card),
+                        shadow: TILE_SHADOW,
+                    };
+                    if tile.opacity < 1. {
+                        queue_caption(
+                            glyph_brush,
+                            &mut self.ellipsized,
+                            &card.title,
+                            &tile,
+                            18.,
+                            rtl,
+                            self.screen_aspect,
+                        );
                     }
-                    if y_pos_pre - scroll_target * v_spacing > safe_area {
-                        scroll_target += 1.;
-                    } else if y_pos_pre - scroll_target * v_spacing < -safe_area {
-                        scroll_target -= 1.;
+                    card_rects.push(((x, y), tile.rect(rtl, self.screen_aspect)));
+                    self.tiles.push(tile);
+                }
+            }
+        }
+        card_rects
+    }
+
+    /// Turn this frame's tiles into instanced batches. Usually everything fits in one;
+    /// a new batch only starts when a tile needs more atlas pages than can be bound at once.
+    pub fn prepare(&mut self, ctxt: &mut GL33Context, texture_host: &mut TextureHost) {
+        self.screen_aspect = framebuffer_aspect(ctxt);
+        let mut batches: Vec<(Vec<TileInstance>, Vec<usize>)> = Vec::new();
+        let mut instances = Vec::new();
+        let mut pages: Vec<usize> = Vec::new();
+        for tile in &self.tiles {
+            let slot = match tile.fill {
+                TileFill::Texture(tex_id) | TileFill::Logo(tex_id) => texture_host.get(tex_id),
+                TileFill::Placeholder | TileFill::Failure => None,
+            };
+            // Textures still waiting on upload fall back to the placeholder,
+            // which also stays underneath while the artwork fades in
+            let under =
+                !matches!(tile.fill, TileFill::Logo(_)) && (slot.is_none() || tile.opacity < 1.);
+            let under_slot = match tile.fill {
+                _ if !under => None,
+                TileFill::Failure => texture_host.get(tex::FALLBACK),
+                _ => texture_host.get(tex::PLACEHOLDER),
+            };
+            let mut needed: Vec<usize> = under_slot.iter().chain(&slot).map(|s| s.page).collect();
+            needed.dedup();
+            let new_pages = needed.iter().filter(|page| !pages.contains(page)).count();
+            if pages.len() + new_pages > MAX_BOUND_PAGES {
+                batches.push((instances, pages));
+                instances = Vec::new();
+                pages = Vec::new();
+            }
+            let mut bind = |page: usize| match pages.iter().position(|&bound| bound == page) {
+                Some(index) => index,
+                None => {
+                    pages.push(page);
+                    pages.len() - 1
+                }
+            };
+
+            let tile_aspect = tile
+                .aspect
+                .or_else(|| slot.map(|slot| slot.aspect))
+                .unwrap_or(TILE_ASPECT);
+            let decorated = tile.focus > 0. || tile.shadow > 0.;
+            let layer = |uv_rect: [f32; 4], uv_scale: [f32; 2], fill: [f32; 4]| TileInstance {
+                position: TilePosition::new([if self.mirrored { -tile.x } else { tile.x }, tile.y]),
+                size: TileSize::new([tile.size * 0.5, tile_aspect]),
+                uv_rect: TileUvRect::new(uv_rect),
+                uv_scale: TileUvScale::new(uv_scale),
+                style: TileStyle::new([
+                    tile.radius,
+                    tile.focus,
+                    tile.shadow,
+                    if decorated { TILE_MARGIN } else { 0. },
+                ]),
+                fill: TileFillParams::new(fill),
+            };
+
+            // Whichever layer comes first draws the shadow and focus ring
+            if let Some(under_slot) = under_slot {
+                let mode = match tile.fill {
+                    TileFill::Failure => MODE_TEXTURE,
+                    _ => MODE_PLACEHOLDER,
+                };
+                instances.push(layer(
+                    under_slot.uv_rect,
+                    [1., 1.],
+                    [
+                        1.,
+                        mode,
+                        bind(under_slot.page) as f32,
+                        decorated as u8 as f32,
+                    ],
+                ));
+            }
+            if let Some(slot) = slot {
+                instances.push(layer(
+                    slot.uv_rect,
+                    tile.fit.uv_scale(tile_aspect, slot.aspect),
+                    [
+                        tile.opacity,
+                        MODE_TEXTURE,
+                        bind(slot.page) as f32,
+                        (decorated && under_slot.is_none()) as u8 as f32,
+                    ],
+                ));
+            }
+        }
+        if !instances.is_empty() {
+            batches.push((instances, pages));
+        }
+
+        // Write into last frame's buffers where they're big enough
+        self.batches.truncate(batches.len());
+        for (i, (instances, pages)) in batches.into_iter().enumerate() {
+            match self.batches.get_mut(i) {
+                Some(batch) if batch.tess.inst_nb() >= instances.len() => {
+                    batch.update(instances, pages)
+                }
+                _ => {
+                    if let Some(batch) = TileBatch::new(ctxt, instances, pages) {
+                        if i < self.batches.len() {
+                            self.batches[i] = batch;
+                        } else {
+                            self.batches.push(batch);
+                        }
                     }
                 }
             }
         }
-        state.scroll_target = scroll_target;
-        state.scroll = lerp(state.scroll, state.scroll_target, 0.9, delta_t);
     }
 
     pub fn render(
         &mut self,
         pipeline: &Pipeline,
         shd_gate: &mut ShadingGate,
-        bindable_textures: &mut HashMap<u32, RGBTexture>,
+        texture_host: &mut TextureHost,
     ) -> Result<(), PipelineError> {
         let program = &mut self.program;
-        let tess = &self.tess;
-        let tiles = &self.tiles;
+        let batches = &self.batches;
+        let render_state = &self.render_state;
+        let time = self.time;
+        let screen_aspect = self.screen_aspect;
         shd_gate.shade(program, |mut iface, uni, mut rdr_gate| {
-            for tile in tiles {
-                let bound_tex = bindable_textures
-                    .get_mut(&tile.tex_id)
-                    .map(|tex| pipeline.bind_texture(tex))
-                    .transpose()?;
-                if let Some(bound_tex) = bound_tex {
-                    iface.set(&uni.tex, bound_tex.binding());
-                    iface.set(&uni.weight, tile.size * 0.5);
-                    iface.set(&uni.position, [tile.x, tile.y]);
-                    rdr_gate.render(&RenderState::default(), |mut tess_gate| {
-                        // let view = TessView::inst_whole(&triangle, panels.len());
-                        tess_gate.render(tess)
-                    })?;
+            iface.set(&uni.time, time);
+            iface.set(&uni.screen_aspect, screen_aspect);
+            iface.set(&uni.focus_color, FOCUS_COLOR);
+            for batch in batches {
+                let bound_pages = texture_host
+                    .pages_mut(&batch.pages)
+                    .into_iter()
+                    .map(|page| pipeline.bind_texture(page))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let page_uniforms = [&uni.page0, &uni.page1, &uni.page2, &uni.page3];
+                for (bound_page, uniform) in bound_pages.iter().zip(page_uniforms.iter()) {
+                    iface.set(uniform, bound_page.binding());
                 }
+                let view = TessView::inst_whole(&batch.tess, batch.len);
+                rdr_gate.render(render_state, |mut tess_gate| tess_gate.render(view))?;
             }
             Ok(())
         })
     }
 }
 
-/// Super basic linear interpolation with delta time
-fn lerp(origin: f32, target: f32, speed: f32, delta_t: f32) -> f32 {
-    origin + (target - origin) * (1. - (1. - delta_t) * speed)
+/// Width over height of what's actually being drawn to, which the window size doesn't always match
+fn framebuffer_aspect(ctxt: &GL33Context) -> f32 {
+    let (width, height) = ctxt.window.get_framebuffer_size();
+    width.max(1) as f32 / height.max(1) as f32
+}
+
+impl TileBatch {
+    /// Make a batch with room to grow, so it can be reused as the number of tiles changes
+    fn new(
+        ctxt: &mut GL33Context,
+        instances: Vec<TileInstance>,
+        pages: Vec<usize>,
+    ) -> Option<Self> {
+        let len = instances.len();
+        let mut buffer = instances;
+        buffer.resize(len.next_power_of_two(), *buffer.first()?);
+        let tess = ctxt
+            .new_tess()
+            .set_render_vertex_nb(4)
+            .set_instances(buffer)
+            .set_mode(Mode::TriangleFan)
+            .build()
+            .map_err(|e| println!("error while building tile batch: {}", e))
+            .ok()?;
+        Some(Self { tess, pages, len })
+    }
+
+    fn update(&mut self, instances: Vec<TileInstance>, pages: Vec<usize>) {
+        let written = self
+            .tess
+            .instances_mut()
+            .map(|mut buffer| buffer[..instances.len()].copy_from_slice(&instances))
+            .map_err(|e| println!("error while updating tile batch: {}", e));
+        self.len = if written.is_ok() { instances.len() } else { 0 };
+        self.pages = pages;
+    }
+}
+
+/// How far a card's focus ring is drawn. Follows the size animation so the ring grows in with the card.
+fn focus_ring(card: &state::Card) -> f32 {
+    ((card.size.value() - TILE_SIZE) / (SELECTED_TILE_SIZE - TILE_SIZE)).clamp(0., 1.)
+}
+
+/// Queue a tile's title in its top left corner, fading out as the artwork fades in
+fn queue_caption(
+    glyph_brush: &mut GlyphBrush<TextInstance>,
+    ellipsized: &mut EllipsisCache,
+    title: &str,
+    tile: &Tile,
+    scale: f32,
+    rtl: bool,
+    screen_aspect: f32,
+) {
+    let weight = tile.size * 0.5;
+    let half_width = tile.half_width(screen_aspect);
+    let padding = scale * 0.5;
+    let width = half_width * 2. * WIDTH as f32 - padding * 2.;
+    let height = weight * 2. * HEIGHT as f32 - padding * 2.;
+    let title = ellipsized.ellipsize(glyph_brush, title, scale, FONT_REGULAR, width);
+    let title = visual_order(title);
+    let color = faded(TITLE_COLOR, 1. - tile.opacity);
+    let section = Section::default()
+        .with_text(styled(glyph_brush, &title, scale, FONT_REGULAR, color))
+        .with_screen_position((
+            (tile.x - half_width + 1.) * WIDTH as f32 + padding,
+            (1. - tile.y - weight) * HEIGHT as f32 + padding,
+        ))
+        .with_bounds((width, height))
+        .with_layout(Layout::default_single_line());
+    glyph_brush.queue(mirrored(section, rtl));
 }
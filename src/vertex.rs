@@ -1,9 +1,9 @@
 use glyph_brush::ab_glyph::{point, Rect};
-use luminance::{pixel::NormRGB8UI, texture::Dim2};
+use luminance::{pixel::NormRGBA8UI, texture::Dim2};
 use luminance_derive::{Semantics, Vertex};
 use luminance_front::texture::Texture;
 
-pub type RGBTexture = Texture<Dim2, NormRGB8UI>;
+pub type RGBATexture = Texture<Dim2, NormRGBA8UI>;
 
 #[derive(Copy, Clone, Debug, Semantics)]
 pub enum VertexSemantics {
@@ -33,6 +33,24 @@ pub enum VertexSemantics {
 
     #[sem(name = "color", repr = "[f32; 4]", wrapper = "TextColor")]
     TextColor,
+
+    #[sem(name = "position", repr = "[f32; 2]", wrapper = "TilePosition")]
+    TilePosition,
+
+    #[sem(name = "size", repr = "[f32; 2]", wrapper = "TileSize")]
+    TileSize,
+
+    #[sem(name = "uv_rect", repr = "[f32; 4]", wrapper = "TileUvRect")]
+    TileUvRect,
+
+    #[sem(name = "uv_scale", repr = "[f32; 2]", wrapper = "TileUvScale")]
+    TileUvScale,
+
+    #[sem(name = "style", repr = "[f32; 4]", wrapper = "TileStyle")]
+    TileStyle,
+
+    #[sem(name = "fill", repr = "[f32; 4]", wrapper = "TileFillParams")]
+    TileFill,
 }
 
 #[derive(Clone, Copy, Debug, Vertex)]
@@ -58,6 +76,26 @@ pub struct TextInstance {
     pub color: TextColor,
 }
 
+/// One tile, or one layer of a tile, drawn by `shader.vert.glsl`
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Vertex, Copy, Debug, Clone)]
+#[vertex(sem = "VertexSemantics", instanced = "true")]
+pub struct TileInstance {
+    /// Center of the tile in NDC
+    pub position: TilePosition,
+    /// Half height in NDC, and width over height
+    pub size: TileSize,
+    /// Where the image lives in its atlas page
+    pub uv_rect: TileUvRect,
+    /// Scale applied to the UVs to fit the image into the tile
+    pub uv_scale: TileUvScale,
+    /// Corner radius, focus strength, shadow opacity and quad margin
+    pub style: TileStyle,
+    /// Opacity, fill mode, atlas page binding and whether to draw decorations
+    pub fill: TileFillParams,
+}
+
 #[inline]
 pub fn to_vertex(
     width: f32,
@@ -66,7 +104,7 @@ pub fn to_vertex(
         mut tex_coords,
         pixel_coords,
         bounds,
-        ..
+        extra,
     }: glyph_brush::GlyphVertex,
 ) -> TextInstance {
     let gl_bounds = bounds;
@@ -112,7 +150,7 @@ pub fn to_vertex(
         right_bottom: VertexRightBottom::new(to_view_space(gl_rect.max.x, gl_rect.min.y)),
         tex_left_top: TextureLeftTop::new([tex_coords.min.x, tex_coords.max.y]),
         tex_right_bottom: TextureRightBottom::new([tex_coords.max.x, tex_coords.min.y]),
-        color: TextColor::new([1., 1., 1., 1.]),
+        color: TextColor::new(extra.color),
     };
 
     // println!("vertex -> {:?}", v);